@@ -1,3 +1,4 @@
+use intcode_computer::Program;
 use std::fs::read_to_string;
 
 const ERR_MSG: &str = "No entry found";
@@ -5,13 +6,10 @@ const EXPECTED_FIRST: i64 = 19_690_720;
 const DELTA: i64 = 331_776;
 
 fn string_to_vec(input: String) -> Vec<i64> {
-    input
-        .trim()
-        .split(',')
-        .collect::<Vec<&str>>()
-        .iter()
-        .map(|opcode| opcode.parse().expect("Not a number"))
-        .collect()
+    Program::parse(&input)
+        .unwrap_or_else(|err| panic!("{}", err))
+        .as_slice()
+        .to_vec()
 }
 
 fn get_parts(i: usize, nums: &[i64]) -> Result<(i64, i64, usize), ()> {
@@ -60,7 +58,7 @@ fn part1() {
     let input_string = read_to_string("input.txt").expect("Failed to open input.txt");
     let nums = string_to_vec(input_string);
     let res = run_parameterized_program(nums, 12, 2).expect("invalid program input");
-    let first = res.get(0).unwrap();
+    let first = res.first().unwrap();
     println!("Solution for part 1 = {}", first);
 }
 
@@ -69,7 +67,7 @@ fn compute_noun(nums: &[i64]) -> Result<i64, ()> {
         let clone = Vec::from(nums);
 
         if let Ok(res) = run_parameterized_program(clone, noun, 0) {
-            let first_entry = *res.get(0).unwrap();
+            let first_entry = *res.first().unwrap();
             let diff = EXPECTED_FIRST - first_entry;
             if diff.abs() < DELTA {
                 // We are close enough to the result here
@@ -86,7 +84,7 @@ fn compute_verb(nums: &[i64], noun: i64) -> Result<i64, ()> {
         let clone = Vec::from(nums);
 
         if let Ok(res) = run_parameterized_program(clone, noun, verb) {
-            let first_entry = *res.get(0).unwrap();
+            let first_entry = *res.first().unwrap();
             let diff = EXPECTED_FIRST - first_entry;
             if diff == 0 {
                 return Ok(verb);
@@ -169,7 +167,7 @@ mod tests {
         let input_string = read_to_string("input.txt").expect("Failed to open input.txt");
         let nums = string_to_vec(input_string);
         let res = run_parameterized_program(nums, 12, 2).expect("invalid program input");
-        let first = res.get(0).unwrap();
+        let first = res.first().unwrap();
         assert_eq!(*first, 6_087_827, "first_entry is not correct")
     }
 
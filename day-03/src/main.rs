@@ -88,7 +88,7 @@ where
     let all_positions: Vec<HashMap<Point, i32>> =
         reader.lines().map(Result::unwrap).map(traverse).collect();
 
-    let first = all_positions.get(0).unwrap();
+    let first = all_positions.first().unwrap();
     let second = all_positions.get(1).unwrap();
     Ok(f(first, second))
 }
@@ -6,7 +6,7 @@ fn num_to_digits(num: u32) -> Vec<u32> {
 }
 
 fn always_increasing(digits: &[u32]) -> bool {
-    let mut prev = u32::min_value();
+    let mut prev = u32::MIN;
     digits.iter().all(|n| {
         if *n < prev {
             return false;
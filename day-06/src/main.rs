@@ -4,7 +4,7 @@ use std::fs::read_to_string;
 type Map<'a> = HashMap<&'a str, &'a str>;
 
 // Maps orbiter-to-orbitee, e.g.: COM)B -> B:COM
-fn get_direct_orbits(input: &str) -> Map {
+fn get_direct_orbits(input: &str) -> Map<'_> {
     input
         .lines()
         .map(|line| line.split(')').collect())
@@ -40,8 +40,8 @@ fn path_to_target<'a>(map: &'a Map, src: &'a str, dest: &'a str) -> Vec<&'a str>
 }
 
 fn find_full_path<'a>(map: &'a Map, src: &'a str, dest: &'a str) -> Vec<&'a str> {
-    let src_to_center = path_to_target(&map, src, "COM");
-    let dest_to_center = path_to_target(&map, dest, "COM");
+    let src_to_center = path_to_target(map, src, "COM");
+    let dest_to_center = path_to_target(map, dest, "COM");
 
     let traverse_to = find_intersection(&src_to_center, &dest_to_center).unwrap_or_else(|| {
         panic!(
@@ -50,8 +50,8 @@ fn find_full_path<'a>(map: &'a Map, src: &'a str, dest: &'a str) -> Vec<&'a str>
         )
     });
 
-    let src_to_inter = path_to_target(&map, "YOU", traverse_to);
-    let mut inter_to_dest = path_to_target(&map, "SAN", traverse_to);
+    let src_to_inter = path_to_target(map, "YOU", traverse_to);
+    let mut inter_to_dest = path_to_target(map, "SAN", traverse_to);
 
     inter_to_dest.reverse();
     // The now first element in `inter_to_dest` is the same as the first element in `src_to_inter`
@@ -79,12 +79,12 @@ fn get_number_of_transfers(map: &Map, src: &str, dest: &str) -> usize {
 }
 
 fn part1(map: &Map) {
-    let count = count_all_orbits(&map);
+    let count = count_all_orbits(map);
     println!("Solution for Part 1 = {}", count);
 }
 
 fn part2(map: &Map) {
-    let number_of_transfers = get_number_of_transfers(&map, "YOU", "SAN");
+    let number_of_transfers = get_number_of_transfers(map, "YOU", "SAN");
     println!("Solution for Part 2 = {}", number_of_transfers);
 }
 
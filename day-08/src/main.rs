@@ -7,11 +7,10 @@ fn part1(layers: &[Vec<u32>]) {
     layers
         .iter()
         .min_by_key(|&layer| layer.iter().filter(|&&n| n == 0).count())
-        .and_then(|layer| {
+        .map(|layer| {
             let num_ones = layer.iter().filter(|&&n| n == 1).count();
             let num_twos = layer.iter().filter(|&&n| n == 2).count();
             println!("Solution for part 1 = {}", num_ones * num_twos);
-            Some(())
         })
         .expect("Failed to find layer");
 }
@@ -1,10 +1,15 @@
-use intcode_computer::run_program;
+use intcode_computer::{Machine, Program};
 use std::fs::read_to_string;
 
 fn main() {
     let program = read_to_string("input.txt").expect("Failed to open input.txt");
-    let (_, output1) = run_program(program.clone(), 1);
+    let program = Program::parse(&program).unwrap_or_else(|err| panic!("{}", err));
+    let mut machine1 = Machine::from_program(&program);
+    machine1.push_input(1);
+    let (_, output1) = machine1.run();
     println!("Solution for part 1 = {:?}", output1[0]);
-    let (_, output2) = run_program(program, 2);
+    let mut machine2 = Machine::from_program(&program);
+    machine2.push_input(2);
+    let (_, output2) = machine2.run();
     println!("Solution for part 2 = {:?}", output2[0]);
 }
@@ -61,7 +61,7 @@ fn vaporize(asteroids: &[Cell], origin: Cell) -> Vec<&Cell> {
         })
         .collect::<Vec<(i64, &Cell)>>();
 
-    asteroids.sort_by(|a, b| a.0.cmp(&b.0));
+    asteroids.sort_by_key(|a| a.0);
 
     let unique_angles = asteroids.iter().map(|a| a.0).collect::<HashSet<i64>>();
 
@@ -81,7 +81,7 @@ fn vaporize(asteroids: &[Cell], origin: Cell) -> Vec<&Cell> {
     angles.sort();
 
     // Reorder, so that first entry is >= 0
-    while let Some(&angle) = angles.get(0) {
+    while let Some(&angle) = angles.first() {
         if angle < 0 {
             angles.push(angle);
             angles.remove(0);
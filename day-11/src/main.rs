@@ -1,8 +1,9 @@
 use crate::Color::{Black, White};
 use crate::Direction::{Down, Left, Right, Up};
-use intcode_computer::Machine;
+use intcode_computer::{InputDevice, Machine, OutputDevice, Program};
 use std::collections::BTreeMap;
 use std::fs::read_to_string;
+use std::sync::{Arc, Mutex};
 
 type Point = (i64, i64);
 
@@ -46,23 +47,37 @@ struct Robot {
     direction: Direction,
     // Keeps a history of colors, for each cell
     cells: BTreeMap<Point, Vec<Color>>,
-    machine: Machine,
+    // The color to paint, until the turn direction is output
+    color_to_paint: Option<Color>,
 }
 
 impl Robot {
-    pub fn new(program: String, starting_color: Color) -> Self {
-        let machine = Machine::new(program, starting_color.to_num());
+    fn new() -> Self {
         Self {
             x: 0,
             y: 0,
             direction: Up,
             cells: BTreeMap::new(),
-            machine,
+            color_to_paint: None,
         }
     }
 
+    /// Runs `program` as the brain of a new robot, which starts on a cell of `starting_color`
+    pub fn paint(program: &Program, starting_color: Color) -> Self {
+        let robot = Arc::new(Mutex::new(Robot::new()));
+        let mut machine = Machine::from_program(program);
+        machine.push_input(starting_color.to_num());
+        machine.set_input_device(Box::new(Arc::clone(&robot)));
+        machine.set_output_device(Box::new(Arc::clone(&robot)));
+        machine.run();
+        Arc::try_unwrap(robot)
+            .expect("Robot is still in use")
+            .into_inner()
+            .expect("Robot lock is poisoned")
+    }
+
     pub fn count_painted_cells(&self) -> usize {
-        self.cells.len() - 1
+        self.cells.len()
     }
 
     pub fn show_result(&self) {
@@ -90,12 +105,6 @@ impl Robot {
         }
     }
 
-    pub fn run(&mut self) {
-        while self.machine.is_running() {
-            self.step();
-        }
-    }
-
     fn turn_left(&mut self) {
         self.direction = match self.direction {
             Up => Left,
@@ -123,15 +132,7 @@ impl Robot {
         }
     }
 
-    fn step(&mut self) {
-        // Get the color to paint
-        self.machine.step();
-        let color_to_paint = (*self.machine.get_output().last().expect("No last entry")).into();
-
-        // Get the turn direction
-        self.machine.step();
-        let turn_amount = self.machine.get_output().last().expect("No last entry");
-
+    fn step(&mut self, color_to_paint: Color, turn_amount: i64) {
         // Paint the current panel
         if let Some(colors) = self.cells.get_mut(&(self.x, self.y)) {
             colors.push(color_to_paint);
@@ -141,7 +142,7 @@ impl Robot {
         }
 
         // Turn the robot
-        match *turn_amount {
+        match turn_amount {
             0 => self.turn_left(),
             1 => self.turn_right(),
             _ => unreachable!("Unexpected turn code"),
@@ -149,26 +150,38 @@ impl Robot {
 
         // Move forward by 1
         self.make_move();
+    }
+}
 
-        // Get the current cell color
-        let input = match self.cells.get(&(self.x, self.y)) {
+/// The camera, which sees the color of the current cell
+impl InputDevice for Robot {
+    fn read(&mut self) -> Option<i64> {
+        let color = match self.cells.get(&(self.x, self.y)) {
             Some(colors) => colors.last().expect("No last entry").to_num(),
             None => Black.to_num(),
         };
+        Some(color)
+    }
+}
 
-        self.machine.push_input(input);
+/// Receives the color to paint, followed by the turn direction
+impl OutputDevice for Robot {
+    fn write(&mut self, value: i64) {
+        match self.color_to_paint.take() {
+            Some(color_to_paint) => self.step(color_to_paint, value),
+            None => self.color_to_paint = Some(value.into()),
+        }
     }
 }
 
 fn main() {
     let program = read_to_string("input.txt").expect("Failed to open input.txt");
-    let mut robot1 = Robot::new(program.clone(), Black);
-    robot1.run();
+    let program = Program::parse(&program).unwrap_or_else(|err| panic!("{}", err));
+    let robot1 = Robot::paint(&program, Black);
     robot1.show_result();
     println!("Solution for part 1: {}", robot1.count_painted_cells());
 
-    let mut robot2 = Robot::new(program, White);
-    robot2.run();
+    let robot2 = Robot::paint(&program, White);
     println!("Solution for part 2: ");
     robot2.show_result();
 }
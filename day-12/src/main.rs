@@ -80,7 +80,7 @@ fn to_positions(input: &str) -> Vec<Moon> {
     input
         .lines()
         .map(|line| {
-            let line = line.replace('>', "").replace('<', "");
+            let line = line.replace(['>', '<'], "");
             let coords: Vec<&str> = line.split(',').collect();
 
             let coords: Vec<i64> = coords
@@ -151,7 +151,7 @@ fn cycle(mut pairs: Vec<(i64, i64)>) -> i64 {
             new_pairs.push((x, dx))
         }
         count += 1;
-        pairs = std::mem::replace(&mut new_pairs, vec![]);
+        pairs = std::mem::take(&mut new_pairs);
         if pairs == target {
             return count;
         }
@@ -1,7 +1,8 @@
 use crate::TileType::{Ball, Block, Empty, HorizontalPaddle, Wall};
-use intcode_computer::Machine;
+use intcode_computer::{InputDevice, Machine, OutputDevice, Program};
 use std::collections::HashMap;
 use std::fs::read_to_string;
+use std::sync::{Arc, Mutex};
 
 type Coords = (i64, i64);
 type Tiles = HashMap<Coords, TileType>;
@@ -36,22 +37,22 @@ enum TileType {
     Ball,
 }
 
-impl Into<TileType> for i64 {
-    fn into(self) -> TileType {
-        match self {
+impl From<i64> for TileType {
+    fn from(i: i64) -> Self {
+        match i {
             0 => Empty,
             1 => Wall,
             2 => Block,
             3 => HorizontalPaddle,
             4 => Ball,
-            _ => unreachable!("Unexpected tile id: {}", self),
+            _ => unreachable!("Unexpected tile id: {}", i),
         }
     }
 }
 
-impl Into<char> for &TileType {
-    fn into(self) -> char {
-        match self {
+impl From<&TileType> for char {
+    fn from(tile_type: &TileType) -> Self {
+        match tile_type {
             Empty => ' ',
             Wall => '_',
             Block => 'X',
@@ -69,78 +70,80 @@ fn get_x_of_tile_type(tiles: &Tiles, tile_type: TileType) -> i64 {
         .unwrap_or(0)
 }
 
-fn part1(program: String) {
-    let mut machine = Machine::new(program, 0);
-    let mut tiles: Tiles = Tiles::new();
-    while machine.is_running() {
-        machine.step();
-        machine.step();
-        machine.step();
-        let out = machine.get_output();
-        let l = out.len();
-        let x = *out.get(l - 3).expect("No output");
-        let y = *out.get(l - 2).expect("No output");
-        let tile_type = *out.get(l - 1).expect("No output");
-        let tile_type = tile_type.into();
-        tiles.insert((x, y), tile_type);
-    }
-
-    tiles.display();
-
-    let num_blocks = tiles
-        .iter()
-        .filter(|t| match t.1 {
-            Block => true,
-            _ => false,
-        })
-        .count();
-
-    println!("Solution for part 1: {}", num_blocks);
+/// The screen of the arcade cabinet, which also drives the joystick
+#[derive(Debug, Default)]
+struct Screen {
+    tiles: Tiles,
+    last_score: Option<i64>,
+    // The values of an incomplete output triple
+    partial: Vec<i64>,
 }
 
-fn part2(program: String) {
-    let mut machine = Machine::new(program.clone(), 0);
-
-    machine.set_memory(0, 2);
-
-    let mut tiles: Tiles = Tiles::new();
-    let mut last_score = i64::min_value();
-    while machine.is_running() {
-        machine.step();
-        machine.step();
-        machine.step();
+impl Screen {
+    /// Runs `program` on a cabinet, returning its screen once the program is done.
+    /// If `free_play` is set, quarters are inserted, and the joystick follows the ball.
+    fn play(program: &Program, free_play: bool) -> Self {
+        let screen = Arc::new(Mutex::new(Screen::default()));
+        let mut machine = Machine::from_program(program);
+        if free_play {
+            machine.set_memory(0, 2);
+            machine.set_input_device(Box::new(Arc::clone(&screen)));
+        }
+        machine.set_output_device(Box::new(Arc::clone(&screen)));
+        machine.run();
+        Arc::try_unwrap(screen)
+            .expect("Screen is still in use")
+            .into_inner()
+            .expect("Screen lock is poisoned")
+    }
+}
 
-        let out = machine.get_output();
-        let l = out.len();
-        let x = *out.get(l - 3).expect("No output");
-        let y = *out.get(l - 2).expect("No output");
-        let third = *out.get(l - 1).expect("No output");
+/// The joystick, which moves the paddle towards the ball
+impl InputDevice for Screen {
+    fn read(&mut self) -> Option<i64> {
+        let ball_x = get_x_of_tile_type(&self.tiles, Ball);
+        let paddle_x = get_x_of_tile_type(&self.tiles, HorizontalPaddle);
+        Some((ball_x - paddle_x).signum())
+    }
+}
 
+/// Receives triples of a position and a tile, or of `(-1, 0)` and the score
+impl OutputDevice for Screen {
+    fn write(&mut self, value: i64) {
+        self.partial.push(value);
+        if self.partial.len() < 3 {
+            return;
+        }
+        let (x, y, third) = (self.partial[0], self.partial[1], self.partial[2]);
+        self.partial.clear();
         if x == -1 && y == 0 {
             println!("Score: {}", third);
-            last_score = third;
+            self.last_score = Some(third);
         } else {
-            let tile_type = third.into();
-            tiles.insert((x, y), tile_type);
-
-            let ball_x = get_x_of_tile_type(&tiles, Ball);
-            let paddle_x = get_x_of_tile_type(&tiles, HorizontalPaddle);
-
-            if paddle_x < ball_x {
-                machine.push_input(1);
-            } else if paddle_x > ball_x {
-                machine.push_input(-1);
-            } else {
-                machine.push_input(0);
-            }
+            self.tiles.insert((x, y), third.into());
         }
     }
+}
+
+fn part1(program: &Program) {
+    let tiles = Screen::play(program, false).tiles;
+
+    tiles.display();
+
+    let num_blocks = tiles.iter().filter(|t| matches!(t.1, Block)).count();
+
+    println!("Solution for part 1: {}", num_blocks);
+}
+
+fn part2(program: &Program) {
+    let last_score = Screen::play(program, true).last_score.unwrap_or(i64::MIN);
 
     println!("Solution for part 2: {}", last_score);
 }
 
 fn main() {
     let program = read_to_string("input.txt").expect("Failed to open input.txt");
-    part1(program.clone());
-    part2(program);
+    let program = Program::parse(&program).unwrap_or_else(|err| panic!("{}", err));
+    part1(&program);
+    part2(&program);
 }
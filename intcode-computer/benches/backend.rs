@@ -0,0 +1,83 @@
+//! Compares running programs with the interpreter, which decodes every instruction when it is
+//! executed, against running them with instructions decoded ahead (`Machine::predecode`).
+//! Predecoding only pays off on long runs, so short programs, like day 5, come out slower.
+//! Run with `cargo bench -p intcode-computer`.
+
+use intcode_computer::{assemble, Machine, Program};
+use std::time::{Duration, Instant};
+
+/// The number of runs per program and backend, of which the fastest is reported
+const RUNS: usize = 10;
+
+/// Counts down from 1000000, adding up the numbers
+const COUNTDOWN: &str = "
+        in [n]
+loop:   add [sum], [n], [sum]
+        add [n], #-1, [n]
+        jnz [n], #loop
+        out [sum]
+        hlt
+n:      db 0
+sum:    db 0
+";
+
+/// Like `COUNTDOWN`, but keeps the number in the operand of its first instruction,
+/// so every iteration overwrites a decoded instruction
+const SELF_MODIFYING: &str = "
+        in [n]
+        add [n], #0, [loop+2]
+loop:   add [sum], #0, [sum]
+        add [loop+2], #-1, [loop+2]
+        jnz [loop+2], #loop
+        out [sum]
+        hlt
+n:      db 0
+sum:    db 0
+";
+
+/// Runs `program` with `input` `RUNS` times, and returns the output and the fastest time
+fn measure(program: &Program, input: i64, predecode: bool) -> (Vec<i64>, Duration) {
+    let mut fastest = Duration::MAX;
+    let mut output = vec![];
+    for _ in 0..RUNS {
+        let mut machine = Machine::from_program(program);
+        machine.push_input(input);
+        machine.predecode(predecode);
+        let start = Instant::now();
+        let (_, result) = machine.try_run().unwrap_or_else(|err| panic!("{}", err));
+        fastest = fastest.min(start.elapsed());
+        output = result;
+    }
+    (output, fastest)
+}
+
+fn main() {
+    let day_05 = include_str!("../../day-05/input.txt");
+    let day_09 = include_str!("../../day-09/input.txt");
+    let benchmarks = vec![
+        ("day 5, part 2", Program::parse(day_05).unwrap(), 5),
+        ("day 9, part 2", Program::parse(day_09).unwrap(), 2),
+        ("countdown", assemble(COUNTDOWN).unwrap(), 1_000_000),
+        (
+            "self-modifying",
+            assemble(SELF_MODIFYING).unwrap(),
+            1_000_000,
+        ),
+    ];
+    println!(
+        "{:<16} {:>14} {:>14} {:>8}",
+        "program", "interpreter", "predecoded", "speedup"
+    );
+    for (name, program, input) in benchmarks {
+        let (expected, interpreted) = measure(&program, input, false);
+        let (output, predecoded) = measure(&program, input, true);
+        assert_eq!(output, expected, "{} has a different output", name);
+        println!(
+            "{:<16} {:>14?} {:>14?} {:>7.2}x",
+            name,
+            interpreted,
+            predecoded,
+            interpreted.as_secs_f64() / predecoded.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use crate::error::IntcodeError;
+use crate::machine::{Machine, StepResult};
+
+#[derive(Debug)]
+/// Runs a program, which communicates in lines of ASCII text.
+/// Output values beyond ASCII, such as the answer of a puzzle, are kept apart as results.
+pub struct AsciiMachine {
+    machine: Machine,
+    results: Vec<i64>,
+    out_of_budget: bool,
+}
+
+impl AsciiMachine {
+    pub fn new(machine: Machine) -> Self {
+        Self {
+            machine,
+            results: vec![],
+            out_of_budget: false,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    /// Appends the characters of `line` to the input queue, followed by a newline.
+    pub fn send_line(&mut self, line: &str) {
+        let codes = line.bytes().chain(Some(b'\n')).map(i64::from);
+        self.machine.extend_input(codes);
+    }
+
+    /// Runs the program, until it needs input, halts, or exhausts its budget,
+    /// and returns the text it output.
+    /// Values beyond ASCII are not part of the text, but are added to the results.
+    pub fn run(&mut self) -> Result<String, IntcodeError> {
+        let mut text = String::new();
+        loop {
+            let event = self.machine.try_step()?;
+            self.out_of_budget = event == StepResult::BudgetExhausted;
+            match event {
+                StepResult::Output(value @ 0..=127) => text.push(value as u8 as char),
+                StepResult::Output(value) => self.results.push(value),
+                StepResult::NeedsInput | StepResult::Halted | StepResult::BudgetExhausted => {
+                    return Ok(text)
+                }
+                StepResult::Watchpoint(_) => {}
+            }
+        }
+    }
+
+    /// Returns true, if the last run stopped, because the budget was used up,
+    /// or the deadline passed
+    pub fn is_out_of_budget(&self) -> bool {
+        self.out_of_budget
+    }
+
+    /// Takes the values beyond ASCII, which were output so far
+    pub fn take_results(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.results)
+    }
+}
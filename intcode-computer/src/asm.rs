@@ -0,0 +1,218 @@
+use crate::instruction::{Mode, Opcode};
+use crate::program::Program;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// A line of assembly, which could not be assembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based line of the error
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A number, or a label with an optional offset, e.g. `loop` or `buffer+2`
+#[derive(Debug)]
+enum Value {
+    Number(i64),
+    Label(String, i64),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction(Opcode, Vec<(Mode, Value)>),
+    Data(Vec<Value>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(_, operands) => operands.len() + 1,
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    let s = s.trim();
+    if let Ok(n) = s.parse() {
+        return Ok(Value::Number(n));
+    }
+    let (label, offset) = match s.find(['+', '-']) {
+        Some(i) => {
+            let offset = s[i..]
+                .trim_start_matches('+')
+                .parse()
+                .map_err(|_| format!("Invalid offset: {:?}", &s[i..]))?;
+            (s[..i].trim(), offset)
+        }
+        None => (s, 0),
+    };
+    if !is_identifier(label) {
+        return Err(format!("Invalid value: {:?}", s));
+    }
+    Ok(Value::Label(String::from(label), offset))
+}
+
+fn parse_operand(s: &str) -> Result<(Mode, Value), String> {
+    let s = s.trim();
+    if s.starts_with('[') && s.ends_with(']') {
+        Ok((Mode::Position, parse_value(&s[1..s.len() - 1])?))
+    } else if let Some(value) = s.strip_prefix('#') {
+        Ok((Mode::Immediate, parse_value(value)?))
+    } else if let Some(offset) = s.strip_prefix("rb") {
+        let offset = offset.trim();
+        let value = if offset.is_empty() {
+            Value::Number(0)
+        } else if let Some(value) = offset.strip_prefix('+') {
+            parse_value(value)?
+        } else if offset.starts_with('-') {
+            parse_value(offset)?
+        } else {
+            return Err(format!("Invalid operand: {:?}", s));
+        };
+        Ok((Mode::Relative, value))
+    } else {
+        Err(format!(
+            "Invalid operand: {:?}, expected `[x]`, `#x` or `rb+x`",
+            s
+        ))
+    }
+}
+
+fn parse_statement(s: &str) -> Result<Statement, String> {
+    let (name, rest) = match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    };
+    let arguments: Vec<&str> = if rest.is_empty() {
+        vec![]
+    } else {
+        rest.split(',').collect()
+    };
+
+    if name == "db" || name == "data" {
+        if arguments.is_empty() {
+            return Err(format!("`{}` needs at least one value", name));
+        }
+        let values = arguments
+            .into_iter()
+            .map(parse_value)
+            .collect::<Result<_, _>>()?;
+        return Ok(Statement::Data(values));
+    }
+
+    let opcode =
+        Opcode::from_mnemonic(name).ok_or_else(|| format!("Unknown mnemonic: {:?}", name))?;
+    if arguments.len() != opcode.num_arguments() {
+        return Err(format!(
+            "`{}` takes {} operands, found {}",
+            name,
+            opcode.num_arguments(),
+            arguments.len()
+        ));
+    }
+    let operands = arguments
+        .into_iter()
+        .map(parse_operand)
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(write) = opcode.write_argument() {
+        if operands[write].0 == Mode::Immediate {
+            return Err(format!("Operand {} of `{}` is written to", write + 1, name));
+        }
+    }
+    Ok(Statement::Instruction(opcode, operands))
+}
+
+fn resolve(value: &Value, labels: &HashMap<String, usize>) -> Result<i64, String> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        Value::Label(label, offset) => labels
+            .get(label)
+            .map(|&address| address as i64 + offset)
+            .ok_or_else(|| format!("Unknown label: {:?}", label)),
+    }
+}
+
+/// Assembles `text` into a program.
+///
+/// Each line holds any number of `label:` definitions, followed by an optional statement:
+/// either an instruction like `add [x], #5, rb+3` or data like `db 1, 2, loop`.
+/// Everything from a `;` to the end of the line is a comment.
+/// Operands are `[x]` for position, `#x` for immediate and `rb+x` for relative mode,
+/// where `x` is a number, or a label with an optional offset, like `buffer+2`.
+pub fn assemble(text: &str) -> Result<Program, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = vec![];
+    let mut address = 0;
+
+    for (line_i, line) in text.lines().enumerate() {
+        let error = |message| AsmError {
+            line: line_i + 1,
+            message,
+        };
+        let mut line = line.split(';').next().unwrap_or_default().trim();
+
+        while let Some(i) = line.find(':') {
+            let label = line[..i].trim();
+            if !is_identifier(label) {
+                break;
+            }
+            if labels.insert(String::from(label), address).is_some() {
+                return Err(error(format!("Duplicate label: {:?}", label)));
+            }
+            line = line[i + 1..].trim();
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+        let statement = parse_statement(line).map_err(error)?;
+        address += statement.size();
+        statements.push((line_i, statement));
+    }
+
+    let mut code = Vec::with_capacity(address);
+    for (line_i, statement) in statements {
+        let error = |message| AsmError {
+            line: line_i + 1,
+            message,
+        };
+        match statement {
+            Statement::Instruction(opcode, operands) => {
+                let modes = operands
+                    .iter()
+                    .rev()
+                    .fold(0, |modes, (mode, _)| modes * 10 + mode.code());
+                code.push(modes * 100 + opcode.code());
+                for (_, value) in &operands {
+                    code.push(resolve(value, &labels).map_err(error)?);
+                }
+            }
+            Statement::Data(values) => {
+                for value in &values {
+                    code.push(resolve(value, &labels).map_err(error)?);
+                }
+            }
+        }
+    }
+    Ok(Program::from(code))
+}
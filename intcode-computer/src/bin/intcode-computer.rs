@@ -1,8 +1,379 @@
-use intcode_computer::run_program;
-use std::fs::read_to_string;
+use intcode_computer::{
+    decompile, disassemble, observed_jumps, read_binary_trace, AsciiMachine, Cfg, Coverage,
+    Debugger, Machine, Program, Snapshot, StepResult, TraceFormat, TraceWriter,
+};
+use std::env;
+use std::fs::{read_to_string, File};
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::process::exit;
+use std::sync::{Arc, Mutex};
 
-fn main() {
-    let program = read_to_string("input2.txt").expect("Failed to open file");
-    let (_, output) = run_program(program, 15);
+const USAGE: &str = "\
+Usage: intcode-computer [OPTIONS] [FILE]
+
+Runs FILE (default: input2.txt), and prints its output.
+The program runs until it halts, or needs more input than was given.
+
+Options:
+  --input VALUES        comma separated input values (default: 15, or none when resuming).
+                        With --coverage, it can be given repeatedly, for a run per list
+  --disasm              print the disassembled program, instead of running it
+  --cfg                 print the control-flow graph of FILE in the DOT language
+  --decompile           print FILE as structured pseudocode
+  --seed-trace PATH     with --cfg or --decompile, also follow the jumps in the binary
+                        trace at PATH
+  --debug               run the program in the debugger, driven by commands from stdin
+  --profile             run the program, and print the disassembly of FILE annotated with
+                        execution counts, followed by the hottest addresses
+  --coverage PATH       run the program, and print the disassembly of FILE annotated with
+                        the executed instructions and data cells, and write an lcov
+                        summary to PATH
+  --ascii               exchange lines of text between the program and the terminal,
+                        printing values beyond ASCII as results (no default input)
+  --resume PATH         continue from the snapshot at PATH, instead of starting FILE
+  --save-snapshot PATH  save the state of the machine to PATH, once it stops
+  --budget N            stop after executing N instructions
+  --predecode           decode every instruction once, instead of on every execution,
+                        which only pays off on long runs
+  --trace PATH          write a JSON lines trace of every executed instruction to PATH,
+                        flagging self-modifying instructions (not with --coverage)
+  --trace-binary PATH   write a binary trace of every executed instruction to PATH";
+
+enum Command {
+    Run,
+    Disasm,
+    Cfg,
+    Decompile,
+    Debug,
+    Ascii,
+    Profile,
+    Coverage(String),
+}
+
+struct Options {
+    path: String,
+    command: Command,
+    // Only the last one is used, unless collecting coverage
+    inputs: Vec<Vec<i64>>,
+    resume: Option<String>,
+    save_snapshot: Option<String>,
+    budget: Option<u64>,
+    predecode: bool,
+    seed_trace: Option<String>,
+    trace: Option<(String, TraceFormat)>,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    exit(1);
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        path: String::from("input2.txt"),
+        command: Command::Run,
+        inputs: vec![],
+        resume: None,
+        save_snapshot: None,
+        budget: None,
+        predecode: false,
+        seed_trace: None,
+        trace: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("{} needs a value\n{}", arg, USAGE)))
+        };
+        match arg.as_str() {
+            "--input" => {
+                let input = Program::parse(&value())
+                    .unwrap_or_else(|err| fail(&format!("Invalid input: {}", err)));
+                options.inputs.push(input.as_slice().to_vec());
+            }
+            "--resume" => options.resume = Some(value()),
+            "--save-snapshot" => options.save_snapshot = Some(value()),
+            "--budget" => {
+                let budget = value()
+                    .parse()
+                    .unwrap_or_else(|_| fail(&format!("Invalid budget\n{}", USAGE)));
+                options.budget = Some(budget);
+            }
+            "--predecode" => options.predecode = true,
+            "--disasm" => options.command = Command::Disasm,
+            "--cfg" => options.command = Command::Cfg,
+            "--decompile" => options.command = Command::Decompile,
+            "--seed-trace" => options.seed_trace = Some(value()),
+            "--debug" => options.command = Command::Debug,
+            "--ascii" => options.command = Command::Ascii,
+            "--profile" => options.command = Command::Profile,
+            "--coverage" => options.command = Command::Coverage(value()),
+            "--trace" | "--trace-binary" => {
+                let path = value();
+                let format = match arg.as_str() {
+                    "--trace" => TraceFormat::JsonLines,
+                    _ => TraceFormat::Binary,
+                };
+                options.trace = Some((path, format));
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            _ if arg.starts_with('-') => fail(&format!("Unknown option: {}\n{}", arg, USAGE)),
+            _ => options.path = arg,
+        }
+    }
+    let runs_once = matches!(
+        options.command,
+        Command::Run | Command::Debug | Command::Ascii | Command::Profile
+    );
+    if options.trace.is_some() && !runs_once {
+        fail(&format!(
+            "--trace needs a single run of the program\n{}",
+            USAGE
+        ));
+    }
+    options
+}
+
+fn load_program(path: &str) -> Program {
+    let text = read_to_string(path)
+        .unwrap_or_else(|err| fail(&format!("Failed to open {}: {}", path, err)));
+    Program::parse(&text).unwrap_or_else(|err| fail(&format!("{}: {}", path, err)))
+}
+
+/// Creates the machine, either from the program, or from the snapshot to resume
+fn load_machine(options: &Options) -> Machine {
+    load_machine_with_input(options, options.inputs.last())
+}
+
+/// Like `load_machine`, but with `input` instead of the last input option
+fn load_machine_with_input(options: &Options, input: Option<&Vec<i64>>) -> Machine {
+    let mut machine = match &options.resume {
+        Some(path) => {
+            let snapshot = Snapshot::load(path)
+                .unwrap_or_else(|err| fail(&format!("Failed to load {}: {}", path, err)));
+            Machine::from_snapshot(&snapshot)
+        }
+        None => Machine::from_program(&load_program(&options.path)),
+    };
+    match input {
+        Some(input) => machine.extend_input(input.iter().copied()),
+        None if options.resume.is_none() && !matches!(options.command, Command::Ascii) => {
+            machine.push_input(15)
+        }
+        None => {}
+    }
+    machine.set_budget(options.budget);
+    machine.predecode(options.predecode);
+    machine
+}
+
+type TraceFile = Arc<Mutex<TraceWriter<BufWriter<File>>>>;
+
+/// Attaches a tracer to `machine`, which writes the trace file, if requested
+fn start_trace(options: &Options, machine: &mut Machine) -> Option<TraceFile> {
+    let (path, format) = options.trace.as_ref()?;
+    let file = File::create(path)
+        .unwrap_or_else(|err| fail(&format!("Failed to create {}: {}", path, err)));
+    let tracer = Arc::new(Mutex::new(TraceWriter::new(BufWriter::new(file), *format)));
+    machine.set_tracer(Box::new(Arc::clone(&tracer)));
+    machine.detect_self_modification(true);
+    Some(tracer)
+}
+
+/// Detaches the tracer from `machine`, and flushes the trace file
+fn finish_trace(options: &Options, machine: &mut Machine, tracer: Option<TraceFile>) {
+    if let (Some(tracer), Some((path, _))) = (tracer, &options.trace) {
+        machine.take_tracer();
+        Arc::try_unwrap(tracer)
+            .expect("The tracer is still attached")
+            .into_inner()
+            .expect("Tracer lock is poisoned")
+            .finish()
+            .unwrap_or_else(|err| fail(&format!("Failed to write {}: {}", path, err)));
+    }
+}
+
+fn run(options: &Options) {
+    let mut machine = load_machine(options);
+    let tracer = start_trace(options, &mut machine);
+    let output = run_machine(&mut machine);
     println!("{:?}", output);
+    finish_trace(options, &mut machine, tracer);
+    save_snapshot(options, &machine);
+}
+
+/// Runs `machine`, until it stops, and returns its output
+fn run_machine(machine: &mut Machine) -> Vec<i64> {
+    let mut output = vec![];
+    loop {
+        match machine.try_step() {
+            Ok(StepResult::Output(value)) => output.push(value),
+            Ok(StepResult::BudgetExhausted) => {
+                eprintln!("Stopped after {} instructions", machine.get_cycles());
+                break;
+            }
+            Ok(_) => break,
+            Err(err) => fail(&format!("Program failed: {}", err)),
+        }
+    }
+    output
+}
+
+/// Runs the program with the profiler, and prints its output, followed by the report
+fn profile(options: &Options) {
+    let mut machine = load_machine(options);
+    // The memory the machine starts with, which differs from FILE, when resuming
+    let program = machine.snapshot().program();
+    machine.enable_profiler();
+    let tracer = start_trace(options, &mut machine);
+    let output = run_machine(&mut machine);
+    finish_trace(options, &mut machine, tracer);
+    println!("{:?}\n", output);
+    let profile = machine.take_profile().expect("The profiler is enabled");
+    let stdout = stdout();
+    profile
+        .write_report(&program, stdout.lock())
+        .expect("Failed to write to stdout");
+    save_snapshot(options, &machine);
+}
+
+/// Runs the program once per input option, and prints the merged coverage,
+/// writing its lcov summary to `path`
+fn coverage(options: &Options, path: &str) {
+    let runs = match options.inputs.len() {
+        0 => vec![None],
+        _ => options.inputs.iter().map(Some).collect(),
+    };
+    let mut coverage = Coverage::new();
+    // The memory the machines start with, which differs from FILE, when resuming
+    let mut program = None;
+    for input in runs {
+        let mut machine = load_machine_with_input(options, input);
+        program.get_or_insert_with(|| machine.snapshot().program());
+        machine.enable_profiler();
+        println!("{:?}", run_machine(&mut machine));
+        let profile = machine.take_profile().expect("The profiler is enabled");
+        coverage.merge(&profile.coverage());
+    }
+    println!();
+    let program = program.expect("There is at least one run");
+    let stdout = stdout();
+    coverage
+        .write_listing(&program, stdout.lock())
+        .expect("Failed to write to stdout");
+    File::create(path)
+        .and_then(|file| coverage.write_lcov(&program, &options.path, BufWriter::new(file)))
+        .unwrap_or_else(|err| fail(&format!("Failed to write {}: {}", path, err)));
+}
+
+/// Saves the state of `machine`, if requested
+fn save_snapshot(options: &Options, machine: &Machine) {
+    if let Some(path) = &options.save_snapshot {
+        machine
+            .snapshot()
+            .save(path)
+            .unwrap_or_else(|err| fail(&format!("Failed to save {}: {}", path, err)));
+    }
+}
+
+/// Runs the program, sending it the lines entered on stdin, and printing the text it outputs
+fn ascii(options: &Options) {
+    let mut machine = load_machine(options);
+    let tracer = start_trace(options, &mut machine);
+    let mut machine = AsciiMachine::new(machine);
+    let stdin = stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        let text = machine
+            .run()
+            .unwrap_or_else(|err| fail(&format!("Program failed: {}", err)));
+        print!("{}", text);
+        for result in machine.take_results() {
+            println!("Result: {}", result);
+        }
+        stdout().flush().expect("Failed to write to stdout");
+        if machine.is_out_of_budget() {
+            eprintln!(
+                "Stopped after {} instructions",
+                machine.machine().get_cycles()
+            );
+            break;
+        }
+        if !machine.machine().is_running() {
+            break;
+        }
+        match lines.next() {
+            Some(line) => machine.send_line(&line.expect("Failed to read from stdin")),
+            None => break,
+        }
+    }
+    finish_trace(options, machine.machine_mut(), tracer);
+    save_snapshot(options, machine.machine());
+}
+
+fn disasm(program: &Program) {
+    for line in disassemble(program) {
+        println!("{:>6}: {}", line.address, line);
+    }
+}
+
+/// The jumps in the seed trace, if any
+fn seed_jumps(options: &Options) -> Vec<(usize, usize)> {
+    match &options.seed_trace {
+        Some(path) => {
+            let records = File::open(path)
+                .and_then(|file| read_binary_trace(BufReader::new(file)))
+                .unwrap_or_else(|err| fail(&format!("Failed to read {}: {}", path, err)));
+            observed_jumps(&records)
+        }
+        None => vec![],
+    }
+}
+
+/// Prints the control-flow graph of the program, following the jumps of the seed trace
+fn cfg(options: &Options) {
+    let program = load_program(&options.path);
+    let jumps = seed_jumps(options);
+    let stdout = stdout();
+    Cfg::with_observed(&program, &jumps)
+        .write_dot(stdout.lock())
+        .expect("Failed to write to stdout");
+}
+
+/// Prints the program as pseudocode, following the jumps of the seed trace
+fn decompile_program(options: &Options) {
+    let program = load_program(&options.path);
+    print!("{}", decompile(&program, &seed_jumps(options)));
+}
+
+/// Runs the debugger, driven by commands from stdin
+fn debug(options: &Options) {
+    let mut machine = load_machine(options);
+    let tracer = start_trace(options, &mut machine);
+    let mut debugger = Debugger::new(machine);
+    let stdin = stdin();
+    debugger
+        .run_script(stdin.lock(), stdout())
+        .expect("Failed to run the debugger");
+    let mut machine = debugger.into_machine();
+    finish_trace(options, &mut machine, tracer);
+    save_snapshot(options, &machine);
+}
+
+fn main() {
+    let options = parse_options();
+    match options.command {
+        Command::Run => run(&options),
+        Command::Disasm => disasm(&load_program(&options.path)),
+        Command::Cfg => cfg(&options),
+        Command::Decompile => decompile_program(&options),
+        Command::Debug => debug(&options),
+        Command::Ascii => ascii(&options),
+        Command::Profile => profile(&options),
+        Command::Coverage(ref path) => coverage(&options, path),
+    }
 }
@@ -0,0 +1,299 @@
+use crate::disasm::{decode_at, DisasmKind, DisasmLine, Operand};
+use crate::instruction::{Mode, Opcode};
+use crate::program::Program;
+use crate::trace::TraceRecord;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction
+    Next,
+    /// A jump to an immediate target
+    Taken,
+    /// A jump, which was seen in a trace, to a target not known statically
+    Observed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An edge of the control-flow graph, to the block starting at `target`
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A sequence of instructions, which is only entered at its start, and only left at its end
+pub struct BasicBlock {
+    pub start: usize,
+    /// The instructions of the block. If the last line is data, the program would fail there.
+    pub lines: Vec<DisasmLine>,
+    pub successors: Vec<Edge>,
+    /// True, if the block ends with a jump, whose target is not an immediate operand
+    pub unresolved: bool,
+}
+
+impl BasicBlock {
+    /// The address after the last line of the block
+    pub fn end(&self) -> usize {
+        self.lines
+            .last()
+            .map_or(self.start, |line| line.address + line.size())
+    }
+}
+
+/// How control leaves an instruction
+struct Flow {
+    // The next instruction, if execution can continue there
+    next: Option<usize>,
+    // The target of a jump, if it is immediate
+    target: Option<usize>,
+    // True, if the line ends its block
+    ends_block: bool,
+    unresolved: bool,
+}
+
+/// Finds out, where execution can continue after `line`
+fn flow(line: &DisasmLine) -> Flow {
+    let next = line.address + line.size();
+    let (opcode, operands) = match &line.kind {
+        DisasmKind::Instruction { opcode, operands } => (*opcode, operands),
+        DisasmKind::Data(_) => {
+            return Flow {
+                next: None,
+                target: None,
+                ends_block: true,
+                unresolved: false,
+            }
+        }
+    };
+    match opcode {
+        Opcode::JIF | Opcode::JEQ => {
+            // An immediate condition makes the jump unconditional, or a no-op
+            let taken = match operands[0] {
+                Operand {
+                    mode: Mode::Immediate,
+                    value,
+                } => Some((value != 0) == (opcode == Opcode::JIF)),
+                _ => None,
+            };
+            let target = match operands[1] {
+                Operand {
+                    mode: Mode::Immediate,
+                    value,
+                } if value >= 0 => Some(value as usize),
+                _ => None,
+            };
+            Flow {
+                next: if taken == Some(true) {
+                    None
+                } else {
+                    Some(next)
+                },
+                target: if taken == Some(false) { None } else { target },
+                ends_block: true,
+                unresolved: taken != Some(false) && target.is_none(),
+            }
+        }
+        Opcode::BRK => Flow {
+            next: None,
+            target: None,
+            ends_block: true,
+            unresolved: false,
+        },
+        _ => Flow {
+            next: Some(next),
+            target: None,
+            ends_block: false,
+            unresolved: false,
+        },
+    }
+}
+
+/// The jumps in `records`, as pairs of the address of the jump, and its target
+pub fn observed_jumps<'a, I>(records: I) -> Vec<(usize, usize)>
+where
+    I: IntoIterator<Item = &'a TraceRecord>,
+{
+    let jumps: BTreeSet<(usize, usize)> = records
+        .into_iter()
+        .filter(|record| matches!(record.opcode, Opcode::JIF | Opcode::JEQ))
+        .filter_map(|record| {
+            let target = *record.operands.get(1).filter(|&&target| target >= 0)?;
+            Some((record.ip, target as usize))
+        })
+        .collect();
+    jumps.into_iter().collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The control-flow graph of a program, found by decoding it statically from address 0,
+/// and following the jumps with immediate targets.
+pub struct Cfg {
+    blocks: BTreeMap<usize, BasicBlock>,
+}
+
+impl Cfg {
+    pub fn new(program: &Program) -> Self {
+        Self::with_observed(program, &[])
+    }
+
+    /// Like `new`, but also follows `jumps`, as pairs of the address of a jump and its target.
+    /// See `observed_jumps`, to take them from a trace.
+    pub fn with_observed(program: &Program, jumps: &[(usize, usize)]) -> Self {
+        Self::with_entries(program, &[], jumps)
+    }
+
+    /// Like `with_observed`, but also decodes from `entries`, which are reached in ways,
+    /// which are not known statically, such as returns from calls.
+    pub fn with_entries(program: &Program, entries: &[usize], jumps: &[(usize, usize)]) -> Self {
+        let code = program.as_slice();
+        let mut observed: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &(ip, target) in jumps {
+            observed.entry(ip).or_default().push(target);
+        }
+
+        // Find the reachable instructions, and where blocks start
+        let mut leaders: BTreeSet<usize> = BTreeSet::new();
+        let mut reached = BTreeSet::new();
+        let mut todo = vec![0];
+        leaders.insert(0);
+        for target in jumps
+            .iter()
+            .map(|&(_, target)| target)
+            .chain(entries.iter().copied())
+        {
+            leaders.insert(target);
+            todo.push(target);
+        }
+        while let Some(address) = todo.pop() {
+            if !reached.insert(address) {
+                continue;
+            }
+            let line = match decode_at(code, address) {
+                Some(line) => line,
+                None => continue,
+            };
+            let flow = flow(&line);
+            if let Some(target) = flow.target {
+                leaders.insert(target);
+                todo.push(target);
+            }
+            if let Some(next) = flow.next {
+                if flow.ends_block {
+                    leaders.insert(next);
+                }
+                todo.push(next);
+            }
+        }
+
+        // Collect the instructions of every block, up to the start of the next one
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut block = BasicBlock {
+                start,
+                lines: vec![],
+                successors: vec![],
+                unresolved: false,
+            };
+            let mut address = start;
+            while let Some(line) = decode_at(code, address) {
+                let flow = flow(&line);
+                block.lines.push(line);
+                if let Some(target) = flow.target {
+                    block.successors.push(Edge {
+                        target,
+                        kind: EdgeKind::Taken,
+                    });
+                }
+                let targets = observed.get(&address).map_or(&[][..], Vec::as_slice);
+                for &target in targets {
+                    if flow.target != Some(target) {
+                        block.successors.push(Edge {
+                            target,
+                            kind: EdgeKind::Observed,
+                        });
+                    }
+                }
+                block.unresolved = flow.unresolved;
+                match flow.next {
+                    Some(next) if flow.ends_block || leaders.contains(&next) => {
+                        block.successors.push(Edge {
+                            target: next,
+                            kind: EdgeKind::Next,
+                        });
+                        break;
+                    }
+                    Some(next) => address = next,
+                    None => break,
+                }
+            }
+            blocks.insert(start, block);
+        }
+        Self { blocks }
+    }
+
+    /// The blocks, ordered by their start
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks.values()
+    }
+
+    /// The block starting at `start`, if any
+    pub fn block(&self, start: usize) -> Option<&BasicBlock> {
+        self.blocks.get(&start)
+    }
+
+    /// The addresses of the jumps, whose targets are not immediate operands
+    pub fn unresolved(&self) -> Vec<usize> {
+        self.blocks()
+            .filter(|block| block.unresolved)
+            .filter_map(|block| Some(block.lines.last()?.address))
+            .collect()
+    }
+
+    /// Writes the graph in the DOT language of Graphviz.
+    /// Unresolved jumps are drawn in red, with a dotted edge to an unknown target,
+    /// and edges seen in a trace are dashed.
+    pub fn write_dot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "digraph cfg {{")?;
+        writeln!(writer, "    node [shape=box, fontname=\"monospace\"];")?;
+        for block in self.blocks() {
+            let mut label: String = block
+                .lines
+                .iter()
+                .map(|line| format!("{}: {}\\l", line.address, line))
+                .collect();
+            if block.lines.is_empty() {
+                label = format!("{}: beyond the program", block.start);
+            }
+            let color = if block.unresolved { ", color=red" } else { "" };
+            writeln!(
+                writer,
+                "    b{} [label=\"{}\"{}];",
+                block.start, label, color
+            )?;
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::Next => "",
+                    EdgeKind::Taken => " [label=\"jump\"]",
+                    EdgeKind::Observed => " [label=\"jump\", style=dashed]",
+                };
+                writeln!(writer, "    b{} -> b{}{};", block.start, edge.target, style)?;
+            }
+            if block.unresolved {
+                writeln!(
+                    writer,
+                    "    b{} -> unknown [style=dotted, color=red];",
+                    block.start
+                )?;
+            }
+        }
+        if self.blocks().any(|block| block.unresolved) {
+            writeln!(
+                writer,
+                "    unknown [label=\"?\", shape=circle, color=red];"
+            )?;
+        }
+        writeln!(writer, "}}")
+    }
+}
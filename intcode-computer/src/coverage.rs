@@ -0,0 +1,229 @@
+use crate::cfg::Cfg;
+use crate::disasm::{disassemble, DisasmKind, DisasmLine};
+use crate::instruction::Opcode;
+use crate::program::Program;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The addresses, which one or more runs of a program executed as instructions,
+/// and the cells they read or wrote as data. Created by `Profile::coverage`.
+pub struct Coverage {
+    runs: usize,
+    executed: BTreeMap<usize, u64>,
+    // Taken and not taken counts of conditional jumps
+    branches: BTreeMap<usize, (u64, u64)>,
+    data: BTreeSet<usize>,
+}
+
+impl Coverage {
+    /// Coverage of no runs at all, to merge runs into
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The coverage of a single run
+    pub(crate) fn from_run(
+        executed: BTreeMap<usize, u64>,
+        branches: BTreeMap<usize, (u64, u64)>,
+        data: BTreeSet<usize>,
+    ) -> Self {
+        Self {
+            runs: 1,
+            executed,
+            branches,
+            data,
+        }
+    }
+
+    /// Adds the runs of `other`, summing up the counts
+    pub fn merge(&mut self, other: &Coverage) {
+        self.runs += other.runs;
+        for (&address, &count) in &other.executed {
+            *self.executed.entry(address).or_default() += count;
+        }
+        for (&address, &(taken, not_taken)) in &other.branches {
+            let branch = self.branches.entry(address).or_default();
+            branch.0 += taken;
+            branch.1 += not_taken;
+        }
+        self.data.extend(&other.data);
+    }
+
+    /// The number of merged runs
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    /// The number of times, the instruction at `address` was executed
+    pub fn executions(&self, address: usize) -> u64 {
+        self.executed.get(&address).copied().unwrap_or_default()
+    }
+
+    /// Returns true, if an instruction was executed at `address`
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.executed.contains_key(&address)
+    }
+
+    /// Returns true, if the cell at `address` was read or written as data
+    pub fn is_data(&self, address: usize) -> bool {
+        self.data.contains(&address)
+    }
+
+    /// The cells, which were read or written as data, but never executed, in order
+    pub fn data_only(&self) -> Vec<usize> {
+        self.data
+            .iter()
+            .copied()
+            .filter(|&address| !self.is_executed(address))
+            .collect()
+    }
+
+    /// The number of times, the conditional jump at `address` was taken, and not taken
+    pub fn branch(&self, address: usize) -> (u64, u64) {
+        self.branches.get(&address).copied().unwrap_or_default()
+    }
+
+    /// The instructions of `program`, which can be reached from address 0,
+    /// or from any address, which was executed, so data is not counted as code
+    fn reachable(&self, program: &Program) -> Vec<DisasmLine> {
+        let entries: Vec<usize> = self.executed.keys().copied().collect();
+        Cfg::with_entries(program, &entries, &[])
+            .blocks()
+            .flat_map(|block| block.lines.iter().cloned())
+            .filter(|line| matches!(line.kind, DisasmKind::Instruction { .. }))
+            .collect()
+    }
+
+    /// The addresses of the reachable instructions of `program`,
+    /// along with any other addresses, which were executed.
+    fn instructions(&self, program: &Program) -> BTreeSet<usize> {
+        self.reachable(program)
+            .into_iter()
+            .map(|line| line.address)
+            .chain(self.executed.keys().copied())
+            .collect()
+    }
+
+    /// The addresses of the reachable conditional jumps of `program`,
+    /// and of any other executed ones
+    fn conditional_jumps(&self, program: &Program) -> BTreeSet<usize> {
+        self.reachable(program)
+            .into_iter()
+            .filter(|line| match line.kind {
+                DisasmKind::Instruction { opcode, .. } => {
+                    matches!(opcode, Opcode::JIF | Opcode::JEQ)
+                }
+                DisasmKind::Data(_) => false,
+            })
+            .map(|line| line.address)
+            .chain(self.branches.keys().copied())
+            .collect()
+    }
+
+    /// Writes the disassembly of `program`, marking every line with its execution count,
+    /// `#####` for reachable instructions, which were never executed, or `-` for data.
+    /// Lines with cells, which were accessed as data, are noted, followed by a summary.
+    pub fn write_listing<W: Write>(&self, program: &Program, mut writer: W) -> io::Result<()> {
+        let instructions = self.instructions(program);
+        for line in disassemble(program) {
+            let mut notes = vec![];
+            let mark = match line.kind {
+                DisasmKind::Instruction { opcode, .. } if self.is_executed(line.address) => {
+                    if matches!(opcode, Opcode::JIF | Opcode::JEQ) {
+                        let (taken, not_taken) = self.branch(line.address);
+                        notes.push(format!("taken {}, not taken {}", taken, not_taken));
+                    }
+                    self.executions(line.address).to_string()
+                }
+                DisasmKind::Instruction { .. } if instructions.contains(&line.address) => {
+                    String::from("#####")
+                }
+                _ => String::from("-"),
+            };
+            let cells = line.address..line.address + line.size();
+            if cells.into_iter().any(|address| self.is_data(address)) {
+                notes.push(String::from("data"));
+            }
+            let notes = if notes.is_empty() {
+                String::new()
+            } else {
+                format!("  ; {}", notes.join(", "))
+            };
+            writeln!(
+                writer,
+                "{:>10} {:>6}: {}{}",
+                mark, line.address, line, notes
+            )?;
+        }
+
+        let jumps = self.conditional_jumps(program);
+        let (taken, not_taken) = jumps.iter().fold((0, 0), |(taken, not_taken), &address| {
+            let (t, n) = self.branch(address);
+            (taken + (t > 0) as usize, not_taken + (n > 0) as usize)
+        });
+        writeln!(
+            writer,
+            "\nExecuted {} of {} instructions ({}) in {} run{}",
+            self.executed.len(),
+            instructions.len(),
+            percentage(self.executed.len(), instructions.len()),
+            self.runs,
+            if self.runs == 1 { "" } else { "s" }
+        )?;
+        writeln!(
+            writer,
+            "Took {} of {} branches ({})",
+            taken + not_taken,
+            2 * jumps.len(),
+            percentage(taken + not_taken, 2 * jumps.len())
+        )?;
+        writeln!(
+            writer,
+            "Accessed {} cells as data only",
+            self.data_only().len()
+        )
+    }
+
+    /// Writes a summary in the format of lcov tracefiles, with addresses as line numbers,
+    /// and both directions of every conditional jump as a branch.
+    pub fn write_lcov<W: Write>(
+        &self,
+        program: &Program,
+        name: &str,
+        mut writer: W,
+    ) -> io::Result<()> {
+        writeln!(writer, "TN:")?;
+        writeln!(writer, "SF:{}", name)?;
+        let jumps = self.conditional_jumps(program);
+        let mut hit = 0;
+        for &address in &jumps {
+            let (taken, not_taken) = self.branch(address);
+            for (block, count) in [taken, not_taken].iter().enumerate() {
+                if !self.is_executed(address) {
+                    writeln!(writer, "BRDA:{},0,{},-", address, block)?;
+                } else {
+                    writeln!(writer, "BRDA:{},0,{},{}", address, block, count)?;
+                    hit += (*count > 0) as usize;
+                }
+            }
+        }
+        writeln!(writer, "BRF:{}", 2 * jumps.len())?;
+        writeln!(writer, "BRH:{}", hit)?;
+        let instructions = self.instructions(program);
+        for &address in &instructions {
+            writeln!(writer, "DA:{},{}", address, self.executions(address))?;
+        }
+        writeln!(writer, "LF:{}", instructions.len())?;
+        writeln!(writer, "LH:{}", self.executed.len())?;
+        writeln!(writer, "end_of_record")
+    }
+}
+
+/// Formats `part` of `total` as a percentage, with one decimal
+fn percentage(part: usize, total: usize) -> String {
+    match total {
+        0 => String::from("-"),
+        _ => format!("{:.1}%", 100.0 * part as f64 / total as f64),
+    }
+}
@@ -0,0 +1,444 @@
+use crate::cfg::Cfg;
+use crate::disasm::{decode_at, DisasmLine};
+use crate::error::IntcodeError;
+use crate::machine::{Executed, Machine, StepResult};
+use crate::program::Program;
+use crate::smc::find_shared_cells;
+use crate::watch::{Access, WatchKind};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+/// The number of instructions shown by `list`, by default
+const LIST_LENGTH: usize = 8;
+
+/// The largest number of instructions or cells, which `list` and `x` show at once
+const MAX_SHOWN: usize = 1 << 16;
+
+/// The number of instructions, which can be stepped back over
+const UNDO_CAPACITY: usize = 1 << 20;
+
+const HELP: &str = "\
+Commands:
+  b ADDR          set a breakpoint at ADDR
+  d ADDR          delete the breakpoint at ADDR
+  bl              list breakpoints
+  w ADDR [N]      stop when one of N cells from ADDR is written (default 1)
+  rw ADDR [N]     stop when one of N cells from ADDR is read
+  aw ADDR [N]     stop when one of N cells from ADDR is read or written
+  wl              list watchpoints
+  wc              delete all watchpoints
+  sc              list cells, which the reachable code in memory uses as both code and data
+  s [N]           execute N single instructions (default 1)
+  n               run until the next output, input request or halt
+  c               continue until a breakpoint, watchpoint, input request or halt
+  rs [N]          step back over N instructions (default 1)
+  rc              run backwards until a breakpoint or the start of the history
+  l [ADDR] [N]    disassemble N instructions from ADDR (default: the instruction pointer)
+  x ADDR [N]      show N memory cells from ADDR (default 1)
+  set ADDR VAL    write VAL to memory at ADDR
+  i VAL...        push values to the input queue
+  o               show all output so far
+  r               show the instruction pointer and relative base
+  q               quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The reason, why the debugger stopped running the machine
+pub enum Stop {
+    Breakpoint(usize),
+    Event(StepResult),
+}
+
+/// A failed command, either because of the user, or because the output could not be written
+enum CommandError {
+    Io(io::Error),
+    User(String),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::Io(err)
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::User(message)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::User(String::from(message))
+    }
+}
+
+impl From<IntcodeError> for CommandError {
+    fn from(err: IntcodeError) -> Self {
+        CommandError::User(err.to_string())
+    }
+}
+
+type CommandResult = Result<(), CommandError>;
+
+#[derive(Debug)]
+/// An interactive debugger, which controls a machine through text commands.
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+}
+
+impl Debugger {
+    /// Creates a debugger for `machine`, which records the last instructions it executes,
+    /// so they can be stepped back over, and reports self-modifying code.
+    pub fn new(mut machine: Machine) -> Self {
+        machine.enable_undo(UNDO_CAPACITY);
+        machine.detect_self_modification(true);
+        Self {
+            machine,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Executes a single instruction
+    pub fn step_instruction(&mut self) -> Result<Executed, IntcodeError> {
+        self.machine.execute_one()
+    }
+
+    /// Reverts the last executed instruction.
+    /// Returns false, if there is no recorded instruction to revert.
+    pub fn step_back(&mut self) -> bool {
+        self.machine.step_back()
+    }
+
+    /// Reverts instructions, until the machine is back at a breakpoint.
+    /// Returns the breakpoint, or `None` if the start of the recorded history was reached first.
+    pub fn run_back_to_breakpoint(&mut self) -> Option<usize> {
+        while self.machine.step_back() {
+            let ip = self.machine.get_instruction_pointer();
+            if self.breakpoints.contains(&ip) {
+                return Some(ip);
+            }
+        }
+        None
+    }
+
+    /// Runs the machine, until it reaches a breakpoint, hits a watchpoint, needs input, halts,
+    /// or exhausts its budget. If `stop_on_output` is set, it also stops after an output.
+    /// A breakpoint at the current instruction is ignored, so a stopped machine can be resumed.
+    fn run_until<F: FnMut(i64)>(
+        &mut self,
+        stop_on_output: bool,
+        mut on_output: F,
+    ) -> Result<Stop, IntcodeError> {
+        let mut first = true;
+        loop {
+            if let Some(hit) = self.machine.take_watch_hit() {
+                return Ok(Stop::Event(StepResult::Watchpoint(hit)));
+            }
+            let ip = self.machine.get_instruction_pointer();
+            if !first && self.breakpoints.contains(&ip) {
+                return Ok(Stop::Breakpoint(ip));
+            }
+            if self.machine.is_running() && self.machine.is_out_of_budget(first) {
+                return Ok(Stop::Event(StepResult::BudgetExhausted));
+            }
+            first = false;
+            match self.machine.execute_one()?.effect.event() {
+                Some(StepResult::Output(value)) => {
+                    on_output(value);
+                    if stop_on_output {
+                        return Ok(Stop::Event(StepResult::Output(value)));
+                    }
+                }
+                Some(event) => return Ok(Stop::Event(event)),
+                None => {}
+            }
+        }
+    }
+
+    /// Runs the machine, until it reaches a breakpoint, hits a watchpoint, needs input, or halts.
+    pub fn cont(&mut self) -> Result<Stop, IntcodeError> {
+        self.run_until(false, |_| {})
+    }
+
+    /// Disassembles up to `count` instructions, starting at `address`,
+    /// stopping at the memory limit
+    pub fn disassemble(&self, mut address: usize, count: usize) -> Vec<DisasmLine> {
+        let mut lines = vec![];
+        while lines.len() < count && self.machine.get_memory(address).is_some() {
+            // Enough cells for an instruction with 3 arguments
+            let cells: Vec<i64> = (address..address.saturating_add(4))
+                .map(|i| self.machine.get_memory(i).unwrap_or_default())
+                .collect();
+            let mut line = match decode_at(&cells, 0) {
+                Some(line) => line,
+                None => break,
+            };
+            line.address = address;
+            let next = address.checked_add(line.size());
+            lines.push(line);
+            match next {
+                Some(next) => address = next,
+                None => break,
+            }
+        }
+        lines
+    }
+
+    /// Reads commands from `input` until it ends or `q` is entered, writing responses to `output`.
+    pub fn run_script<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        write!(output, "(icdb) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let Some(&"q") = words.first() {
+                break;
+            }
+            match self.execute_command(&words, &mut output) {
+                Ok(()) => {}
+                Err(CommandError::Io(err)) => return Err(err),
+                Err(CommandError::User(message)) => writeln!(output, "{}", message)?,
+            }
+            write!(output, "(icdb) ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+
+    fn execute_command<W: Write>(&mut self, words: &[&str], output: &mut W) -> CommandResult {
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(()),
+        };
+        let args = args
+            .iter()
+            .map(|arg| arg.parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid arguments: {}", args.join(" ")))?;
+        let address = |i: usize| -> Result<usize, String> {
+            match args.get(i) {
+                Some(&arg) if arg >= 0 => Ok(arg as usize),
+                Some(arg) => Err(format!("Invalid address: {}", arg)),
+                None => Err(format!("`{}` needs an address", command)),
+            }
+        };
+        let count = |i: usize, default: usize| args.get(i).map_or(default, |&n| n.max(0) as usize);
+        let shown = |i: usize, default: usize| match count(i, default) {
+            n if n > MAX_SHOWN => Err(format!("Can not show more than {} at once", MAX_SHOWN)),
+            n => Ok(n),
+        };
+
+        match command {
+            "b" => {
+                let address = address(0)?;
+                self.add_breakpoint(address);
+                writeln!(output, "Breakpoint at {}", address)?;
+            }
+            "d" => {
+                let address = address(0)?;
+                if !self.remove_breakpoint(address) {
+                    return Err(format!("No breakpoint at {}", address).into());
+                }
+            }
+            "bl" => {
+                for address in &self.breakpoints {
+                    writeln!(output, "Breakpoint at {}", address)?;
+                }
+            }
+            "w" | "rw" | "aw" => {
+                let start = address(0)?;
+                let kind = match command {
+                    "w" => WatchKind::Write,
+                    "rw" => WatchKind::Read,
+                    _ => WatchKind::ReadWrite,
+                };
+                self.machine
+                    .add_watchpoint(start..start.saturating_add(count(1, 1).max(1)), kind);
+            }
+            "wl" => {
+                for (range, kind) in self.machine.watchpoints() {
+                    writeln!(
+                        output,
+                        "Watchpoint on {}..{} ({:?})",
+                        range.start, range.end, kind
+                    )?;
+                }
+            }
+            "wc" => self.machine.clear_watchpoints(),
+            "sc" => {
+                let memory = self.machine.snapshot().memory.into_vec();
+                let cfg = Cfg::new(&Program::from(memory));
+                for cell in find_shared_cells(&cfg) {
+                    let accesses: Vec<String> = cell
+                        .accesses
+                        .iter()
+                        .map(|&(ip, access)| match access {
+                            Access::Read => format!("read at {}", ip),
+                            Access::Write => format!("written at {}", ip),
+                        })
+                        .collect();
+                    writeln!(
+                        output,
+                        "[{}] in the instruction at {}, {}",
+                        cell.address,
+                        cell.instruction,
+                        accesses.join(", ")
+                    )?;
+                }
+            }
+            "s" => {
+                for _ in 0..count(0, 1) {
+                    let event = self.step_instruction()?.effect.event();
+                    self.write_self_modifications(output)?;
+                    let mut stop = false;
+                    while let Some(hit) = self.machine.take_watch_hit() {
+                        self.write_event(StepResult::Watchpoint(hit), output)?;
+                        stop = true;
+                    }
+                    match event {
+                        Some(StepResult::Output(value)) => {
+                            self.write_event(StepResult::Output(value), output)?
+                        }
+                        Some(event) => {
+                            self.write_event(event, output)?;
+                            break;
+                        }
+                        None => {}
+                    }
+                    if stop {
+                        break;
+                    }
+                }
+                self.write_listing(self.machine.get_instruction_pointer(), 1, output)?;
+            }
+            "n" | "c" => {
+                let mut outputs = vec![];
+                let stop = self.run_until(command == "n", |value| outputs.push(value));
+                for value in outputs {
+                    self.write_event(StepResult::Output(value), output)?;
+                }
+                self.write_self_modifications(output)?;
+                match stop? {
+                    Stop::Breakpoint(address) => {
+                        writeln!(output, "Stopped at breakpoint {}", address)?
+                    }
+                    Stop::Event(StepResult::Output(_)) => {}
+                    Stop::Event(event) => self.write_event(event, output)?,
+                }
+                self.write_listing(self.machine.get_instruction_pointer(), 1, output)?;
+            }
+            "rs" => {
+                for _ in 0..count(0, 1) {
+                    if !self.step_back() {
+                        writeln!(output, "Reached the start of the history")?;
+                        break;
+                    }
+                }
+                self.write_listing(self.machine.get_instruction_pointer(), 1, output)?;
+            }
+            "rc" => {
+                match self.run_back_to_breakpoint() {
+                    Some(address) => writeln!(output, "Stopped at breakpoint {}", address)?,
+                    None => writeln!(output, "Reached the start of the history")?,
+                }
+                self.write_listing(self.machine.get_instruction_pointer(), 1, output)?;
+            }
+            "l" => {
+                let start = match args.first() {
+                    Some(_) => address(0)?,
+                    None => self.machine.get_instruction_pointer(),
+                };
+                self.write_listing(start, shown(1, LIST_LENGTH)?, output)?;
+            }
+            "x" => {
+                let start = address(0)?;
+                let cells: Vec<String> = (start..start.saturating_add(shown(1, 1)?))
+                    .map(|i| match self.machine.get_memory(i) {
+                        Some(value) => value.to_string(),
+                        None => String::from("?"),
+                    })
+                    .collect();
+                writeln!(output, "{}: {}", start, cells.join(" "))?;
+            }
+            "set" => {
+                let address = address(0)?;
+                let value = *args.get(1).ok_or("`set` needs a value")?;
+                if self.machine.get_memory(address).is_none() {
+                    return Err(format!("Invalid address: {}", address).into());
+                }
+                self.machine.set_memory(address, value);
+            }
+            "i" => self.machine.extend_input(args),
+            "o" => writeln!(output, "{:?}", self.machine.get_output())?,
+            "r" => writeln!(
+                output,
+                "ip={} rb={} halted={} waiting={}",
+                self.machine.get_instruction_pointer(),
+                self.machine.get_relative_base(),
+                !self.machine.is_running(),
+                self.machine.is_waiting_for_input()
+            )?,
+            "h" | "help" => writeln!(output, "{}", HELP)?,
+            _ => return Err(format!("Unknown command: {}, try `h`", command).into()),
+        }
+        Ok(())
+    }
+
+    fn write_event<W: Write>(&self, event: StepResult, output: &mut W) -> io::Result<()> {
+        match event {
+            StepResult::Output(value) => writeln!(output, "Output: {}", value),
+            StepResult::NeedsInput => writeln!(output, "Waiting for input"),
+            StepResult::Halted => writeln!(output, "Halted"),
+            StepResult::Watchpoint(hit) => writeln!(output, "Watchpoint: {}", hit),
+            StepResult::BudgetExhausted => writeln!(output, "Budget exhausted"),
+        }
+    }
+
+    /// Writes the modifications of code, which were not reported yet
+    fn write_self_modifications<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        while let Some(event) = self.machine.take_self_modification() {
+            writeln!(output, "Self-modifying code: {}", event)?;
+        }
+        Ok(())
+    }
+
+    fn write_listing<W: Write>(
+        &self,
+        start: usize,
+        count: usize,
+        output: &mut W,
+    ) -> io::Result<()> {
+        let ip = self.machine.get_instruction_pointer();
+        for line in self.disassemble(start, count) {
+            let marker = if line.address == ip { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&line.address) {
+                "*"
+            } else {
+                " "
+            };
+            writeln!(
+                output,
+                "{}{}{:>5}: {}",
+                marker, breakpoint, line.address, line
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1037 @@
+use crate::cfg::{BasicBlock, Cfg, EdgeKind};
+use crate::disasm::{DisasmKind, DisasmLine, Operand};
+use crate::instruction::{Mode, Opcode};
+use crate::program::Program;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl BinOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Lt => "<",
+            BinOp::Ge => ">=",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Mul => 3,
+            BinOp::Add | BinOp::Sub => 2,
+            _ => 1,
+        }
+    }
+
+    /// The comparison, which is true whenever this one is false
+    fn negated(self) -> Option<BinOp> {
+        match self {
+            BinOp::Lt => Some(BinOp::Ge),
+            BinOp::Ge => Some(BinOp::Lt),
+            BinOp::Eq => Some(BinOp::Ne),
+            BinOp::Ne => Some(BinOp::Eq),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Const(i64),
+    /// A fixed memory cell
+    Var(i64),
+    /// A cell relative to the relative base
+    Frame(i64),
+    Input,
+    Neg(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn bin(op: BinOp, left: Expr, right: Expr) -> Expr {
+        Expr::Bin(op, Box::new(left), Box::new(right))
+    }
+
+    fn from_operand(operand: Operand) -> Expr {
+        match operand.mode {
+            Mode::Position => Expr::Var(operand.value),
+            Mode::Immediate => Expr::Const(operand.value),
+            Mode::Relative => Expr::Frame(operand.value),
+        }
+    }
+
+    fn has_input(&self) -> bool {
+        match self {
+            Expr::Input => true,
+            Expr::Neg(expr) => expr.has_input(),
+            Expr::Bin(_, left, right) => left.has_input() || right.has_input(),
+            _ => false,
+        }
+    }
+
+    /// Adds the fixed cells, which the expression reads, to `vars`
+    fn vars(&self, vars: &mut Vec<i64>) {
+        match self {
+            Expr::Var(address) => vars.push(*address),
+            Expr::Neg(expr) => expr.vars(vars),
+            Expr::Bin(_, left, right) => {
+                left.vars(vars);
+                right.vars(vars);
+            }
+            _ => {}
+        }
+    }
+
+    /// Replaces the only read of `var` with `value`
+    fn substitute(&mut self, var: i64, value: &Expr) {
+        match self {
+            Expr::Var(address) if *address == var => *self = value.clone(),
+            Expr::Neg(expr) => expr.substitute(var, value),
+            Expr::Bin(_, left, right) => {
+                left.substitute(var, value);
+                right.substitute(var, value);
+            }
+            _ => {}
+        }
+    }
+
+    /// The expression, which is true whenever this one is false
+    fn negate(self) -> Expr {
+        match self {
+            Expr::Bin(op, left, right) if op.negated().is_some() => {
+                Expr::Bin(op.negated().unwrap_or(op), left, right)
+            }
+            expr => Expr::bin(BinOp::Eq, expr, Expr::Const(0)),
+        }
+    }
+
+    fn is_comparison(&self) -> bool {
+        matches!(self, Expr::Bin(op, _, _) if op.precedence() == 1)
+    }
+
+    /// Folds constants, and removes operations, which do nothing
+    fn simplify(self) -> Expr {
+        let (op, left, right) = match self {
+            Expr::Neg(expr) => {
+                return match expr.simplify() {
+                    Expr::Const(value) => Expr::Const(value.wrapping_neg()),
+                    Expr::Neg(expr) => *expr,
+                    expr => Expr::Neg(Box::new(expr)),
+                }
+            }
+            Expr::Bin(op, left, right) => (op, left.simplify(), right.simplify()),
+            expr => return expr,
+        };
+        match (op, left, right) {
+            (BinOp::Add, Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_add(b)),
+            (BinOp::Sub, Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_sub(b)),
+            (BinOp::Mul, Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_mul(b)),
+            (BinOp::Lt, Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as i64),
+            (BinOp::Ge, Expr::Const(a), Expr::Const(b)) => Expr::Const((a >= b) as i64),
+            (BinOp::Eq, Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as i64),
+            (BinOp::Ne, Expr::Const(a), Expr::Const(b)) => Expr::Const((a != b) as i64),
+            (BinOp::Add, Expr::Const(0), expr) | (BinOp::Add, expr, Expr::Const(0)) => expr,
+            (BinOp::Mul, Expr::Const(1), expr) | (BinOp::Mul, expr, Expr::Const(1)) => expr,
+            (BinOp::Mul, Expr::Const(-1), expr) | (BinOp::Mul, expr, Expr::Const(-1)) => {
+                Expr::Neg(Box::new(expr)).simplify()
+            }
+            (BinOp::Mul, Expr::Const(0), expr) | (BinOp::Mul, expr, Expr::Const(0))
+                if !expr.has_input() =>
+            {
+                Expr::Const(0)
+            }
+            (BinOp::Add, expr, Expr::Const(value)) if value < 0 => {
+                Expr::bin(BinOp::Sub, expr, Expr::Const(value.wrapping_neg()))
+            }
+            (BinOp::Add, Expr::Neg(negated), expr) => Expr::bin(BinOp::Sub, expr, *negated),
+            (BinOp::Add, expr, Expr::Neg(negated)) => Expr::bin(BinOp::Sub, expr, *negated),
+            // A comparison is 1 or 0, so comparing it with 0 is the comparison, or its negation
+            (BinOp::Ne, expr, Expr::Const(0)) if expr.is_comparison() => expr,
+            (BinOp::Eq, expr, Expr::Const(0)) if expr.is_comparison() => expr.negate(),
+            (op, left, right) => Expr::bin(op, left, right),
+        }
+    }
+
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, parent: u8) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Var(address) => write!(f, "v{}", address),
+            Expr::Frame(offset) => write!(f, "fp[{}]", offset),
+            Expr::Input => write!(f, "input()"),
+            Expr::Neg(expr) => {
+                write!(f, "-")?;
+                expr.fmt_with(f, 4)
+            }
+            Expr::Bin(op, left, right) => {
+                let precedence = op.precedence();
+                if precedence < parent {
+                    write!(f, "(")?;
+                }
+                left.fmt_with(f, precedence)?;
+                write!(f, " {} ", op.symbol())?;
+                // Operators of the same precedence are evaluated from left to right
+                right.fmt_with(f, precedence + 1)?;
+                if precedence < parent {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Address(usize),
+    Indirect(Expr),
+}
+
+impl Target {
+    fn from_operand(operand: Operand) -> Target {
+        match operand {
+            Operand {
+                mode: Mode::Immediate,
+                value,
+            } if value >= 0 => Target::Address(value as usize),
+            operand => Target::Indirect(Expr::from_operand(operand)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stmt {
+    /// Stores the value in the cell, given as a `Var` or `Frame` expression
+    Assign(Expr, Expr),
+    Output(Expr),
+    AdjustRb(Expr),
+    Jump(Target),
+    /// Jumps, if the condition is true
+    CondJump(Expr, Target),
+    Call(usize, Vec<Expr>),
+    Return,
+    Halt,
+    /// A cell, which is not a valid instruction
+    Invalid(i64),
+}
+
+impl Stmt {
+    fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Stmt::Assign(_, expr) | Stmt::Output(expr) | Stmt::AdjustRb(expr) => vec![expr],
+            Stmt::Jump(Target::Indirect(target)) => vec![target],
+            Stmt::CondJump(cond, Target::Indirect(target)) => vec![cond, target],
+            Stmt::CondJump(cond, _) => vec![cond],
+            Stmt::Call(_, args) => args.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The fixed cells, which the statement reads, with repetitions
+    fn uses(&mut self) -> Vec<i64> {
+        let mut vars = vec![];
+        for expr in self.exprs_mut() {
+            expr.vars(&mut vars);
+        }
+        vars
+    }
+
+    /// The fixed cell, which the statement writes
+    fn def(&self) -> Option<i64> {
+        match self {
+            Stmt::Assign(Expr::Var(address), _) => Some(*address),
+            _ => None,
+        }
+    }
+
+    fn simplify(&mut self) {
+        for expr in self.exprs_mut() {
+            *expr = std::mem::replace(expr, Expr::Const(0)).simplify();
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Assign(place, value) => write!(f, "{} = {};", place, value),
+            Stmt::Output(value) => write!(f, "output({});", value),
+            Stmt::AdjustRb(Expr::Const(value)) if *value < 0 => write!(f, "rb -= {};", -value),
+            Stmt::AdjustRb(value) => write!(f, "rb += {};", value),
+            Stmt::Jump(Target::Address(address)) => write!(f, "goto L{};", address),
+            Stmt::Jump(Target::Indirect(target)) => write!(f, "goto *{};", target),
+            Stmt::CondJump(cond, target) => {
+                write!(f, "if ({}) ", cond)?;
+                Stmt::Jump(target.clone()).fmt(f)
+            }
+            Stmt::Call(function, args) => {
+                let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+                write!(f, "f{}({});", function, args.join(", "))
+            }
+            Stmt::Return => write!(f, "return;"),
+            Stmt::Halt => write!(f, "halt;"),
+            Stmt::Invalid(value) => write!(f, "invalid({});", value),
+        }
+    }
+}
+
+/// Turns a decoded line into a statement, if it does anything
+fn lift(line: &DisasmLine) -> Option<Stmt> {
+    let (opcode, operands) = match &line.kind {
+        DisasmKind::Instruction { opcode, operands } => (*opcode, operands),
+        DisasmKind::Data(value) => return Some(Stmt::Invalid(*value)),
+    };
+    let arg = |i: usize| Expr::from_operand(operands[i]);
+    let binary = |op| Expr::bin(op, arg(0), arg(1));
+    let stmt = match opcode {
+        Opcode::ADD => Stmt::Assign(arg(2), binary(BinOp::Add)),
+        Opcode::MUL => Stmt::Assign(arg(2), binary(BinOp::Mul)),
+        Opcode::LT => Stmt::Assign(arg(2), binary(BinOp::Lt)),
+        Opcode::EQ => Stmt::Assign(arg(2), binary(BinOp::Eq)),
+        Opcode::IN => Stmt::Assign(arg(0), Expr::Input),
+        Opcode::OUT => Stmt::Output(arg(0)),
+        Opcode::RB => Stmt::AdjustRb(arg(0)),
+        Opcode::BRK => Stmt::Halt,
+        Opcode::JIF | Opcode::JEQ => {
+            let target = Target::from_operand(operands[1]);
+            let op = if opcode == Opcode::JIF {
+                BinOp::Ne
+            } else {
+                BinOp::Eq
+            };
+            match Expr::bin(op, arg(0), Expr::Const(0)).simplify() {
+                Expr::Const(0) => return None,
+                Expr::Const(_) => match target {
+                    // Returns jump to the address, which the caller stored in the frame
+                    Target::Indirect(Expr::Frame(_)) => Stmt::Return,
+                    target => Stmt::Jump(target),
+                },
+                cond => Stmt::CondJump(cond, target),
+            }
+        }
+    };
+    let mut stmt = stmt;
+    stmt.simplify();
+    Some(stmt)
+}
+
+/// A basic block, lifted to statements
+#[derive(Debug, Clone)]
+struct Block {
+    start: usize,
+    stmts: Vec<Stmt>,
+    /// Where execution continues after the last statement, if it does not jump.
+    /// For a call, this is the address it returns to.
+    next: Option<usize>,
+    /// The targets of an indirect jump at the end, which were seen in a trace
+    observed: Vec<usize>,
+}
+
+impl Block {
+    fn new(block: &BasicBlock) -> Self {
+        let mut stmts: Vec<Stmt> = block.lines.iter().filter_map(lift).collect();
+        let next = match stmts.last() {
+            Some(Stmt::Jump(_))
+            | Some(Stmt::Return)
+            | Some(Stmt::Halt)
+            | Some(Stmt::Invalid(_)) => None,
+            _ if block.lines.is_empty() => None,
+            _ => Some(block.end()),
+        };
+        let observed = match stmts.last() {
+            Some(Stmt::Jump(Target::Indirect(_)))
+            | Some(Stmt::CondJump(_, Target::Indirect(_))) => {
+                let edges = block.successors.iter();
+                let observed = edges.filter(|edge| edge.kind == EdgeKind::Observed);
+                observed.map(|edge| edge.target).collect()
+            }
+            _ => vec![],
+        };
+        let mut lifted = Block {
+            start: block.start,
+            stmts: vec![],
+            next,
+            observed,
+        };
+        // An unconditional jump after storing the address following it is a call,
+        // with the values stored after the return address as its arguments.
+        if let Some(Stmt::Jump(Target::Address(function))) = stmts.last() {
+            let function = *function;
+            let end = block.end() as i64;
+            let stored = stmts.iter().rposition(
+                |stmt| matches!(stmt, Stmt::Assign(_, Expr::Const(value)) if *value == end),
+            );
+            if let Some(i) = stored {
+                let slot = match &stmts[i] {
+                    Stmt::Assign(Expr::Frame(slot), _) => Some(*slot),
+                    _ => None,
+                };
+                stmts.pop();
+                stmts.remove(i);
+                let mut args = vec![];
+                if let Some(slot) = slot {
+                    // Take the stores to the following slots, which directly precede the jump
+                    while let Some(Stmt::Assign(Expr::Frame(arg), _)) = stmts.last() {
+                        if *arg <= slot || args.iter().any(|(a, _)| a == arg) {
+                            break;
+                        }
+                        if let Some(Stmt::Assign(Expr::Frame(arg), value)) = stmts.pop() {
+                            args.push((arg, value));
+                        }
+                    }
+                    args.reverse();
+                    // Arguments must be stored to consecutive slots in order,
+                    // otherwise they are kept as stores
+                    let ordered = (slot + 1..)
+                        .zip(&args)
+                        .all(|(expected, (arg, _))| expected == *arg);
+                    if !ordered {
+                        let stores = args
+                            .drain(..)
+                            .map(|(arg, value)| Stmt::Assign(Expr::Frame(arg), value));
+                        stmts.extend(stores);
+                    }
+                }
+                stmts.push(Stmt::Call(
+                    function,
+                    args.into_iter().map(|(_, value)| value).collect(),
+                ));
+                lifted.next = Some(block.end());
+            }
+        }
+        lifted.stmts = stmts;
+        lifted
+    }
+
+    /// The function, which the block calls, if any
+    fn callee(&self) -> Option<usize> {
+        match self.stmts.last() {
+            Some(Stmt::Call(function, _)) => Some(*function),
+            _ => None,
+        }
+    }
+
+    /// The addresses, which execution continues at after the block, within its function
+    fn successors(&self) -> Vec<usize> {
+        let mut successors = vec![];
+        match self.stmts.last() {
+            Some(Stmt::Jump(Target::Address(target)))
+            | Some(Stmt::CondJump(_, Target::Address(target))) => successors.push(*target),
+            _ => {}
+        }
+        successors.extend(self.next);
+        successors.extend(&self.observed);
+        successors
+    }
+
+    /// Returns true, if the block jumps to `address` directly
+    fn jumps_to(&self, address: usize) -> bool {
+        !self.observed.contains(&address) && self.successors().contains(&address)
+    }
+
+    /// Returns true, if execution may leave the function at the end of the block
+    fn returns(&self) -> bool {
+        matches!(
+            self.stmts.last(),
+            Some(Stmt::Return)
+                | Some(Stmt::Jump(Target::Indirect(_)))
+                | Some(Stmt::CondJump(_, Target::Indirect(_)))
+        )
+    }
+}
+
+/// A set of fixed cells, which may also be every cell
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Live {
+    All,
+    Some(BTreeSet<i64>),
+}
+
+impl Live {
+    fn none() -> Self {
+        Live::Some(BTreeSet::new())
+    }
+
+    fn contains(&self, var: i64) -> bool {
+        match self {
+            Live::All => true,
+            Live::Some(vars) => vars.contains(&var),
+        }
+    }
+
+    fn union(&mut self, other: &Live) {
+        match (&mut *self, other) {
+            (Live::All, _) => {}
+            (_, Live::All) => *self = Live::All,
+            (Live::Some(vars), Live::Some(others)) => vars.extend(others),
+        }
+    }
+
+    /// Updates the set from after `stmt` to before it
+    fn step_back(&mut self, stmt: &Stmt, calls: &BTreeMap<usize, Live>) {
+        if let (Some(var), Live::Some(vars)) = (stmt.def(), &mut *self) {
+            vars.remove(&var);
+        }
+        if let Stmt::Call(function, _) = stmt {
+            self.union(calls.get(function).unwrap_or(&Live::All));
+        }
+        let mut stmt = stmt.clone();
+        if let Live::Some(vars) = self {
+            vars.extend(stmt.uses());
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Function {
+    entry: usize,
+    /// The size of the stack frame, which the function allocates on entry
+    frame: Option<i64>,
+    /// The blocks of the function, ordered by their start
+    blocks: Vec<Block>,
+}
+
+/// The control-flow statements of structured pseudocode
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Label(usize),
+    Stmt(Stmt),
+    If(Expr, Vec<Node>, Vec<Node>),
+    Loop(Vec<Node>),
+    While(Expr, Vec<Node>),
+    DoWhile(Vec<Node>, Expr),
+    Break,
+    Continue,
+}
+
+/// Where jumps leave a region of blocks
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    /// The address, which follows the region
+    exit: usize,
+    /// The exit of the innermost loop
+    brk: Option<usize>,
+    /// The start of the innermost loop
+    cont: Option<usize>,
+}
+
+impl Region {
+    /// The jump to `address`, or nothing, if it is the end of the region anyway
+    fn jump(&self, address: usize, last: bool, gotos: &mut BTreeSet<usize>) -> Option<Node> {
+        if last && address == self.exit {
+            None
+        } else if Some(address) == self.cont {
+            Some(Node::Continue)
+        } else if Some(address) == self.brk {
+            Some(Node::Break)
+        } else {
+            gotos.insert(address);
+            Some(Node::Stmt(Stmt::Jump(Target::Address(address))))
+        }
+    }
+}
+
+/// Recovers `if` and loop statements from the blocks of a function
+struct Structurer<'a> {
+    function: &'a Function,
+    gotos: BTreeSet<usize>,
+}
+
+impl<'a> Structurer<'a> {
+    fn start(&self, i: usize) -> usize {
+        self.function.blocks[i].start
+    }
+
+    /// Structures the blocks `lo..hi`. If `header` is set, the first block is the start
+    /// of a loop, which was already recognized.
+    fn region(&mut self, lo: usize, hi: usize, region: Region, header: bool) -> Vec<Node> {
+        let blocks = &self.function.blocks;
+        let mut nodes = vec![];
+        let mut i = lo;
+        while i < hi {
+            let block = &blocks[i];
+            if !(header && i == lo) {
+                // The last block, which jumps back to this one, ends a loop
+                if let Some(end) = (i..hi).rev().find(|&b| blocks[b].jumps_to(block.start)) {
+                    let exit = if end + 1 < hi {
+                        self.start(end + 1)
+                    } else {
+                        region.exit
+                    };
+                    let inner = Region {
+                        exit: block.start,
+                        brk: Some(exit),
+                        cont: Some(block.start),
+                    };
+                    let body = self.region(i, end + 1, inner, true);
+                    nodes.push(Node::Loop(body));
+                    i = end + 1;
+                    if i == hi && exit != region.exit {
+                        nodes.extend(region.jump(exit, true, &mut self.gotos));
+                    }
+                    continue;
+                }
+            }
+
+            nodes.push(Node::Label(block.start));
+            let (body, last) = match block.stmts.split_last() {
+                Some((last, body)) => (body, Some(last)),
+                None => (&block.stmts[..], None),
+            };
+            nodes.extend(body.iter().cloned().map(Node::Stmt));
+            let is_last = i + 1 == hi;
+            match last {
+                Some(Stmt::CondJump(cond, Target::Address(target))) => {
+                    let target = *target;
+                    let falls_through = !is_last && block.next == Some(self.start(i + 1));
+                    let then_end = (i + 2..=hi).find(|&j| {
+                        j < hi && self.start(j) == target || j == hi && target == region.exit
+                    });
+                    if let (true, Some(j)) = (falls_through, then_end) {
+                        let cond = cond.clone().negate().simplify();
+                        // The then branch jumps over the else branch
+                        let else_end = match blocks[j - 1].stmts.last() {
+                            Some(Stmt::Jump(Target::Address(end))) if j < hi => (j + 1..=hi)
+                                .find(|&k| {
+                                    k < hi && self.start(k) == *end
+                                        || k == hi && *end == region.exit
+                                })
+                                .map(|k| (k, *end)),
+                            _ => None,
+                        };
+                        match else_end {
+                            Some((k, end)) => {
+                                let then = self.region(
+                                    i + 1,
+                                    j,
+                                    Region {
+                                        exit: end,
+                                        ..region
+                                    },
+                                    false,
+                                );
+                                let other = self.region(
+                                    j,
+                                    k,
+                                    Region {
+                                        exit: end,
+                                        ..region
+                                    },
+                                    false,
+                                );
+                                nodes.push(Node::If(cond, then, other));
+                                i = k;
+                            }
+                            None => {
+                                let then = self.region(
+                                    i + 1,
+                                    j,
+                                    Region {
+                                        exit: target,
+                                        ..region
+                                    },
+                                    false,
+                                );
+                                nodes.push(Node::If(cond, then, vec![]));
+                                i = j;
+                            }
+                        }
+                        if i == hi && target != region.exit && else_end.is_none() {
+                            nodes.extend(region.jump(target, true, &mut self.gotos));
+                        }
+                        continue;
+                    }
+                    let jump = region.jump(target, false, &mut self.gotos);
+                    nodes.push(Node::If(cond.clone(), jump.into_iter().collect(), vec![]));
+                }
+                Some(Stmt::Jump(Target::Address(target))) => {
+                    nodes.extend(region.jump(*target, is_last, &mut self.gotos));
+                }
+                Some(stmt) => {
+                    // Indirect jumps show where they went in the trace as labels
+                    self.gotos.extend(&block.observed);
+                    nodes.push(Node::Stmt(stmt.clone()));
+                }
+                None => {}
+            }
+            if let Some(next) = block.next {
+                if is_last || next != self.start(i + 1) {
+                    nodes.extend(region.jump(next, is_last, &mut self.gotos));
+                }
+            }
+            i += 1;
+        }
+        nodes
+    }
+}
+
+/// Turns loops, which test their condition at the start or at the end,
+/// into `while` and `do`-`while` loops, and removes unused labels
+fn tidy(nodes: Vec<Node>, gotos: &BTreeSet<usize>) -> Vec<Node> {
+    let mut tidied = vec![];
+    for node in nodes {
+        match node {
+            Node::Label(address) if !gotos.contains(&address) => {}
+            Node::If(cond, then, other) => {
+                tidied.push(Node::If(cond, tidy(then, gotos), tidy(other, gotos)))
+            }
+            Node::Loop(body) => {
+                let mut body = tidy(body, gotos);
+                // Labels at the start of the loop are kept in front of it
+                let labels = body
+                    .iter()
+                    .take_while(|n| matches!(n, Node::Label(_)))
+                    .count();
+                tidied.extend(body.drain(..labels));
+                match (body.first(), body.len()) {
+                    (Some(Node::If(cond, then, other)), _)
+                        if then == &[Node::Break] && other.is_empty() =>
+                    {
+                        let cond = cond.clone().negate().simplify();
+                        tidied.push(Node::While(cond, body.split_off(1)));
+                    }
+                    (_, len) if len >= 2 && body[len - 1] == Node::Break => match &body[len - 2] {
+                        Node::If(cond, then, other)
+                            if then == &[Node::Continue] && other.is_empty() =>
+                        {
+                            let cond = cond.clone();
+                            body.truncate(len - 2);
+                            tidied.push(Node::DoWhile(body, cond));
+                        }
+                        _ => tidied.push(Node::Loop(body)),
+                    },
+                    _ => tidied.push(Node::Loop(body)),
+                }
+            }
+            node => tidied.push(node),
+        }
+    }
+    tidied
+}
+
+fn write_nodes(out: &mut String, nodes: &[Node], depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        match node {
+            Node::Label(address) => writeln!(out, "{}L{}:", "    ".repeat(depth - 1), address)?,
+            Node::Stmt(stmt) => writeln!(out, "{}{}", indent, stmt)?,
+            Node::If(cond, then, other) => {
+                writeln!(out, "{}if ({}) {{", indent, cond)?;
+                write_nodes(out, then, depth + 1)?;
+                if !other.is_empty() {
+                    writeln!(out, "{}}} else {{", indent)?;
+                    write_nodes(out, other, depth + 1)?;
+                }
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::Loop(body) => {
+                writeln!(out, "{}loop {{", indent)?;
+                write_nodes(out, body, depth + 1)?;
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::While(cond, body) => {
+                writeln!(out, "{}while ({}) {{", indent, cond)?;
+                write_nodes(out, body, depth + 1)?;
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::DoWhile(body, cond) => {
+                writeln!(out, "{}do {{", indent)?;
+                write_nodes(out, body, depth + 1)?;
+                writeln!(out, "{}}} while ({});", indent, cond)?;
+            }
+            Node::Break => writeln!(out, "{}break;", indent)?,
+            Node::Continue => writeln!(out, "{}continue;", indent)?,
+        }
+    }
+    Ok(())
+}
+
+/// Lifts the blocks of `cfg` into functions: one starting at address 0,
+/// and one for every target of a call.
+fn functions(cfg: &Cfg) -> Vec<Function> {
+    let blocks: BTreeMap<usize, Block> = cfg
+        .blocks()
+        .map(|block| (block.start, Block::new(block)))
+        .collect();
+    let mut entries: BTreeSet<usize> = blocks.values().filter_map(Block::callee).collect();
+    entries.insert(0);
+    entries
+        .into_iter()
+        .filter(|entry| blocks.contains_key(entry))
+        .map(|entry| {
+            let mut reached = BTreeSet::new();
+            let mut todo = vec![entry];
+            while let Some(address) = todo.pop() {
+                if let (true, Some(block)) = (reached.insert(address), blocks.get(&address)) {
+                    todo.extend(block.successors());
+                }
+            }
+            let mut function = Function {
+                entry,
+                frame: None,
+                blocks: reached
+                    .into_iter()
+                    .filter_map(|address| blocks.get(&address).cloned())
+                    .collect(),
+            };
+            remove_frame(&mut function);
+            function
+        })
+        .collect()
+}
+
+/// Removes the allocation of a stack frame on entry to a called function,
+/// and its release before returning
+fn remove_frame(function: &mut Function) {
+    if function.entry == 0 {
+        return;
+    }
+    let size = match function.blocks.iter().find(|b| b.start == function.entry) {
+        Some(Block { stmts, .. }) => match stmts.first() {
+            Some(Stmt::AdjustRb(Expr::Const(size))) if *size > 0 => *size,
+            _ => return,
+        },
+        None => return,
+    };
+    let releases_frame = |block: &Block| {
+        let len = block.stmts.len();
+        len >= 2
+            && block.stmts[len - 1] == Stmt::Return
+            && block.stmts[len - 2] == Stmt::AdjustRb(Expr::Const(-size))
+    };
+    // The frame is only removed, if every return releases it
+    let returns = function
+        .blocks
+        .iter()
+        .filter(|b| b.stmts.last() == Some(&Stmt::Return));
+    if !returns.clone().all(releases_frame) {
+        return;
+    }
+    for block in &mut function.blocks {
+        if block.start == function.entry {
+            block.stmts.remove(0);
+        }
+        if releases_frame(block) {
+            let len = block.stmts.len();
+            block.stmts.remove(len - 2);
+        }
+    }
+    function.frame = Some(size);
+}
+
+/// Computes the fixed cells, which are live at the end of every block of every function.
+/// A call reads the cells, which are live at the entry of the called function,
+/// and a return reads the cells, which are live where the function returns to.
+/// Also returns the cells, which are live at the entry of every function.
+fn liveness(functions: &[Function]) -> (Vec<BTreeMap<usize, Live>>, BTreeMap<usize, Live>) {
+    let mut entry_live: BTreeMap<usize, Live> = functions
+        .iter()
+        .map(|function| (function.entry, Live::none()))
+        .collect();
+    let mut callers: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
+    for (f, function) in functions.iter().enumerate() {
+        for block in &function.blocks {
+            if let (Some(callee), Some(next)) = (block.callee(), block.next) {
+                callers.entry(callee).or_default().push((f, next));
+            }
+        }
+    }
+    let mut live_in: Vec<BTreeMap<usize, Live>> = functions
+        .iter()
+        .map(|function| {
+            function
+                .blocks
+                .iter()
+                .map(|b| (b.start, Live::none()))
+                .collect()
+        })
+        .collect();
+    // The program may continue anywhere after main returns, or a function without callers
+    let returned_to = |function: &Function, live_in: &[BTreeMap<usize, Live>]| match callers
+        .get(&function.entry)
+    {
+        Some(sites) if function.entry != 0 => {
+            let mut live = Live::none();
+            for &(caller, next) in sites {
+                live.union(live_in[caller].get(&next).unwrap_or(&Live::All));
+            }
+            live
+        }
+        _ => Live::All,
+    };
+    loop {
+        let mut changed = false;
+        for (f, function) in functions.iter().enumerate() {
+            let returned_to = returned_to(function, &live_in);
+            for block in function.blocks.iter().rev() {
+                let mut live = live_out(block, &live_in[f], &returned_to);
+                for stmt in block.stmts.iter().rev() {
+                    live.step_back(stmt, &entry_live);
+                }
+                if live_in[f][&block.start] != live {
+                    live_in[f].insert(block.start, live);
+                    changed = true;
+                }
+            }
+            let entry = live_in[f]
+                .get(&function.entry)
+                .cloned()
+                .unwrap_or(Live::All);
+            if entry_live[&function.entry] != entry {
+                entry_live.insert(function.entry, entry);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    let live_out = functions
+        .iter()
+        .enumerate()
+        .map(|(f, function)| {
+            let returned_to = returned_to(function, &live_in);
+            function
+                .blocks
+                .iter()
+                .map(|block| (block.start, live_out(block, &live_in[f], &returned_to)))
+                .collect()
+        })
+        .collect();
+    (live_out, entry_live)
+}
+
+/// The cells, which are live at the end of `block`
+fn live_out(block: &Block, live_in: &BTreeMap<usize, Live>, returned_to: &Live) -> Live {
+    let mut live = if block.returns() {
+        returned_to.clone()
+    } else {
+        Live::none()
+    };
+    for successor in block.successors() {
+        live.union(live_in.get(&successor).unwrap_or(&Live::All));
+    }
+    live
+}
+
+/// Substitutes the values of fixed cells into the statement, which directly follows,
+/// if they are read only there, building up expressions.
+fn inline(block: &mut Block, live_out: &Live, calls: &BTreeMap<usize, Live>) {
+    let mut i = 0;
+    while i + 1 < block.stmts.len() {
+        let (var, value) = match &block.stmts[i] {
+            Stmt::Assign(Expr::Var(var), value) => (*var, value.clone()),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let mut live = live_out.clone();
+        for stmt in block.stmts[i + 2..].iter().rev() {
+            live.step_back(stmt, calls);
+        }
+        let next = &mut block.stmts[i + 1];
+        let single_use = next.uses().iter().filter(|&&v| v == var).count() == 1;
+        let called = match next {
+            Stmt::Call(function, _) => calls.get(function).unwrap_or(&Live::All).contains(var),
+            _ => false,
+        };
+        let dead = next.def() == Some(var) || !live.contains(var) && !called;
+        // Inputs have to be read in the same order
+        let reorders = value.has_input() && next.exprs_mut().iter().any(|e| e.has_input());
+        if single_use && dead && !reorders {
+            for expr in next.exprs_mut() {
+                expr.substitute(var, &value);
+            }
+            next.simplify();
+            block.stmts.remove(i);
+            i = i.saturating_sub(1);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Decompiles the program of `program` into structured pseudocode.
+///
+/// Fixed memory cells are named `v` and their address, cells relative to the relative base
+/// `fp[offset]`. Jumps to a function after storing their return address in the frame
+/// are shown as calls, with the values stored in the following cells as arguments,
+/// and jumps to an address in the frame as returns. The jumps in `jumps`,
+/// as pairs of the address of a jump and its target, are followed as well.
+pub fn decompile(program: &Program, jumps: &[(usize, usize)]) -> String {
+    // Returns from calls are only reached indirectly, so the code after every call
+    // has to be decoded from there.
+    let mut returns = vec![];
+    let cfg = loop {
+        let cfg = Cfg::with_entries(program, &returns, jumps);
+        let found: Vec<usize> = cfg
+            .blocks()
+            .map(Block::new)
+            .filter(|block| block.callee().is_some())
+            .filter_map(|block| block.next)
+            .filter(|next| !returns.contains(next))
+            .collect();
+        if found.is_empty() {
+            break cfg;
+        }
+        returns.extend(found);
+    };
+
+    let mut functions = functions(&cfg);
+    let (live, calls) = liveness(&functions);
+    for (function, live) in functions.iter_mut().zip(&live) {
+        for block in &mut function.blocks {
+            inline(block, &live[&block.start], &calls);
+        }
+    }
+
+    let mut out = String::new();
+    for function in &functions {
+        let mut structurer = Structurer {
+            function,
+            gotos: BTreeSet::new(),
+        };
+        // Nothing follows a function, so every jump in it is explicit
+        let region = Region {
+            exit: usize::MAX,
+            brk: None,
+            cont: None,
+        };
+        let nodes = structurer.region(0, function.blocks.len(), region, false);
+        let nodes = tidy(nodes, &structurer.gotos);
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let name = match function.entry {
+            0 => String::from("main"),
+            entry => format!("f{}", entry),
+        };
+        writeln!(out, "fn {}() {{", name).ok();
+        if let Some(frame) = function.frame {
+            writeln!(out, "    // frame of {} cells", frame).ok();
+        }
+        write_nodes(&mut out, &nodes, 1).ok();
+        writeln!(out, "}}").ok();
+    }
+    out
+}
@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::io::{self, BufRead, BufReader, Stdin, Write};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+
+/// A source of values for IN instructions. See `Machine::set_input_device`.
+pub trait InputDevice: Debug {
+    /// Returns the next value, or `None` if there is none yet.
+    /// The machine then waits for input, and asks again once it is resumed.
+    fn read(&mut self) -> Option<i64>;
+}
+
+/// A receiver of the values of OUT instructions. See `Machine::set_output_device`.
+pub trait OutputDevice: Debug {
+    fn write(&mut self, value: i64);
+}
+
+/// Allows keeping access to a device, which is attached to a machine
+impl<T: InputDevice> InputDevice for Arc<Mutex<T>> {
+    fn read(&mut self) -> Option<i64> {
+        self.lock().expect("Device lock is poisoned").read()
+    }
+}
+
+/// Allows keeping access to a device, which is attached to a machine
+impl<T: OutputDevice> OutputDevice for Arc<Mutex<T>> {
+    fn write(&mut self, value: i64) {
+        self.lock().expect("Device lock is poisoned").write(value)
+    }
+}
+
+impl InputDevice for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl OutputDevice for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+/// Blocks, until a value is sent. Once every sender is gone, no more input is given.
+impl InputDevice for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Values are dropped, once the receiver is gone
+impl OutputDevice for Sender<i64> {
+    fn write(&mut self, value: i64) {
+        self.send(value).ok();
+    }
+}
+
+/// Blocks, while the channel is full. Values are dropped, once the receiver is gone.
+impl OutputDevice for SyncSender<i64> {
+    fn write(&mut self, value: i64) {
+        self.send(value).ok();
+    }
+}
+
+#[derive(Debug)]
+/// Reads lines of numbers, separated by commas or whitespace.
+/// Tokens which are not numbers are skipped.
+pub struct LineInput<R: BufRead + Debug> {
+    reader: R,
+    values: VecDeque<i64>,
+}
+
+impl<R: BufRead + Debug> LineInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            values: VecDeque::new(),
+        }
+    }
+}
+
+impl LineInput<BufReader<Stdin>> {
+    /// Reads numbers from the lines of stdin
+    pub fn stdin() -> Self {
+        Self::new(BufReader::new(io::stdin()))
+    }
+}
+
+impl<R: BufRead + Debug> InputDevice for LineInput<R> {
+    /// Reads lines, until one with a number is found. Gives no input at the end of the reader.
+    fn read(&mut self) -> Option<i64> {
+        while self.values.is_empty() {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let values = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter_map(|token| token.parse::<i64>().ok());
+            self.values.extend(values);
+        }
+        self.values.pop_front()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Gives the characters of a text as ASCII codes
+pub struct AsciiInput {
+    codes: VecDeque<i64>,
+}
+
+impl AsciiInput {
+    pub fn new(text: &str) -> Self {
+        let mut input = Self::default();
+        input.push_str(text);
+        input
+    }
+
+    /// Appends the characters of `text`, after the ones not read yet
+    pub fn push_str(&mut self, text: &str) {
+        self.codes.extend(text.bytes().map(i64::from));
+    }
+}
+
+impl InputDevice for AsciiInput {
+    fn read(&mut self) -> Option<i64> {
+        self.codes.pop_front()
+    }
+}
+
+#[derive(Debug)]
+/// Writes ASCII codes as characters. Other values are written as a number, on a line of its own.
+/// Since output can not fail, the first I/O error is kept, and can be checked with `finish`.
+pub struct AsciiOutput<W: Write + Debug> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Debug> AsciiOutput<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Flushes the writer, and returns it, or the first error which occurred while writing.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write + Debug> OutputDevice for AsciiOutput<W> {
+    fn write(&mut self, value: i64) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match value {
+            0..=127 => self.writer.write_all(&[value as u8]),
+            _ => writeln!(self.writer, "{}", value),
+        };
+        self.error = result.err();
+    }
+}
+
+/// Gives the values returned by a closure
+pub struct InputFn<F: FnMut() -> Option<i64>>(pub F);
+
+impl<F: FnMut() -> Option<i64>> Debug for InputFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InputFn")
+    }
+}
+
+impl<F: FnMut() -> Option<i64>> InputDevice for InputFn<F> {
+    fn read(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+/// Passes every value to a closure
+pub struct OutputFn<F: FnMut(i64)>(pub F);
+
+impl<F: FnMut(i64)> Debug for OutputFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OutputFn")
+    }
+}
+
+impl<F: FnMut(i64)> OutputDevice for OutputFn<F> {
+    fn write(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
@@ -0,0 +1,105 @@
+use crate::instruction::{Instruction, Mode, Opcode};
+use crate::program::Program;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An argument of a disassembled instruction
+pub struct Operand {
+    pub mode: Mode,
+    pub value: i64,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "#{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "rb{}", self.value),
+            Mode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisasmKind {
+    Instruction {
+        opcode: Opcode,
+        operands: Vec<Operand>,
+    },
+    /// A cell, which does not start a valid instruction
+    Data(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single instruction or data cell of a disassembled program.
+/// It is displayed without its address, e.g. `add [12], #5, rb+3` or `data 42`.
+pub struct DisasmLine {
+    pub address: usize,
+    pub kind: DisasmKind,
+}
+
+impl DisasmLine {
+    /// The number of cells, covered by this line
+    pub fn size(&self) -> usize {
+        match &self.kind {
+            DisasmKind::Instruction { operands, .. } => operands.len() + 1,
+            DisasmKind::Data(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            DisasmKind::Instruction { opcode, operands } => {
+                write!(f, "{}", opcode.mnemonic())?;
+                for (i, operand) in operands.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, operand)?;
+                }
+                Ok(())
+            }
+            DisasmKind::Data(value) => write!(f, "data {}", value),
+        }
+    }
+}
+
+/// Decodes the cell at `address` of `code`, or returns `None` if it is beyond the end.
+/// Falls back to a data cell, if it is not a valid instruction, or its arguments are cut off.
+pub fn decode_at(code: &[i64], address: usize) -> Option<DisasmLine> {
+    let value = *code.get(address)?;
+    let kind = Instruction::new(value)
+        .ok()
+        .and_then(|instruction| {
+            let opcode = instruction.opcode;
+            if let Some(write) = opcode.write_argument() {
+                if instruction.get_mode(write) == Mode::Immediate {
+                    return None;
+                }
+            }
+            let arguments = code.get(address + 1..address + 1 + opcode.num_arguments())?;
+            let operands = arguments
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| Operand {
+                    mode: instruction.get_mode(i),
+                    value,
+                })
+                .collect();
+            Some(DisasmKind::Instruction { opcode, operands })
+        })
+        .unwrap_or(DisasmKind::Data(value));
+    Some(DisasmLine { address, kind })
+}
+
+/// Disassembles `program` from start to end, in a single linear sweep.
+pub fn disassemble(program: &Program) -> Vec<DisasmLine> {
+    let code = program.as_slice();
+    let mut lines = vec![];
+    let mut address = 0;
+    while let Some(line) = decode_at(code, address) {
+        address += line.size();
+        lines.push(line);
+    }
+    lines
+}
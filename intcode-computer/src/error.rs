@@ -0,0 +1,89 @@
+use crate::program::ParseError;
+use std::error::Error;
+use std::fmt;
+
+/// An error, which occurred while loading or running an intcode program.
+/// Runtime errors carry the faulting instruction pointer (`ip`) and the raw opcode found there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntcodeError {
+    /// The program text could not be parsed
+    Parse(ParseError),
+    /// The instruction pointer does not point into memory
+    InstructionPointerOutOfRange { ip: usize },
+    /// The opcode is not one of the known instructions
+    UnknownOpcode { ip: usize, opcode: i64 },
+    /// The mode of the `operand`-th argument is not one of the known modes
+    UnknownMode {
+        ip: usize,
+        opcode: i64,
+        operand: usize,
+    },
+    /// The `operand`-th argument is written to, but is in immediate mode
+    ImmediateWrite {
+        ip: usize,
+        opcode: i64,
+        operand: usize,
+    },
+    /// The `operand`-th argument refers to an address outside of memory
+    InvalidAddress {
+        ip: usize,
+        opcode: i64,
+        operand: usize,
+        address: i64,
+    },
+    /// The result of the instruction does not fit into an `i64`
+    Overflow { ip: usize, opcode: i64 },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::Parse(err) => err.fmt(f),
+            IntcodeError::InstructionPointerOutOfRange { ip } => {
+                write!(f, "Instruction pointer is out of range: {}", ip)
+            }
+            IntcodeError::UnknownOpcode { ip, opcode } => {
+                write!(f, "Unexpected opcode {} at {}", opcode, ip)
+            }
+            IntcodeError::UnknownMode {
+                ip,
+                opcode,
+                operand,
+            } => write!(
+                f,
+                "Unexpected mode for argument {} of opcode {} at {}",
+                operand, opcode, ip
+            ),
+            IntcodeError::ImmediateWrite {
+                ip,
+                opcode,
+                operand,
+            } => write!(
+                f,
+                "Immediate mode for the write argument {} of opcode {} at {}",
+                operand, opcode, ip
+            ),
+            IntcodeError::InvalidAddress {
+                ip,
+                opcode,
+                operand,
+                address,
+            } => write!(
+                f,
+                "Invalid address {} for argument {} of opcode {} at {}",
+                address, operand, opcode, ip
+            ),
+            IntcodeError::Overflow { ip, opcode } => {
+                write!(f, "Arithmetic overflow in opcode {} at {}", opcode, ip)
+            }
+        }
+    }
+}
+
+impl Error for IntcodeError {}
+
+impl From<ParseError> for IntcodeError {
+    fn from(err: ParseError) -> Self {
+        IntcodeError::Parse(err)
+    }
+}
@@ -0,0 +1,256 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// An asynchronous source of values
+pub trait Stream {
+    type Item;
+
+    /// Returns the next value, or `None` once the stream ended.
+    /// If no value is available yet, the task is woken once there is.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+}
+
+/// An asynchronous receiver of values
+pub trait Sink<T> {
+    /// Returns ready, once a value can be sent.
+    /// If it can not be sent yet, the task is woken once it can.
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()>;
+
+    /// Sends `item`. Must only be called after `poll_ready` returned ready.
+    fn start_send(self: Pin<&mut Self>, item: T);
+}
+
+/// A stream of the values of an iterator, which never has to wait
+#[derive(Debug, Clone)]
+pub struct IterStream<I: Iterator>(pub I);
+
+impl<I: Iterator + Unpin> Stream for IterStream<I> {
+    type Item = I::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.0.next())
+    }
+}
+
+impl<T: Unpin> Sink<T> for Vec<T> {
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) {
+        self.get_mut().push(item);
+    }
+}
+
+impl<S: Stream + Unpin + ?Sized> Stream for &mut S {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+}
+
+impl<T, S: Sink<T> + Unpin + ?Sized> Sink<T> for &mut S {
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut **self).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) {
+        Pin::new(&mut **self).start_send(item)
+    }
+}
+
+#[derive(Debug)]
+struct QueueState<T> {
+    values: VecDeque<T>,
+    // The task waiting for a value
+    waker: Option<Waker>,
+    senders: usize,
+}
+
+/// The sending half of a queue, for tasks on the same thread. Sending never has to wait.
+#[derive(Debug)]
+pub struct QueueSender<T>(Rc<RefCell<QueueState<T>>>);
+
+/// The receiving half of a queue, which ends once every sender is dropped
+#[derive(Debug)]
+pub struct QueueReceiver<T>(Rc<RefCell<QueueState<T>>>);
+
+/// Creates an unbounded queue, for passing values between tasks on the same thread
+pub fn queue<T>() -> (QueueSender<T>, QueueReceiver<T>) {
+    let state = Rc::new(RefCell::new(QueueState {
+        values: VecDeque::new(),
+        waker: None,
+        senders: 1,
+    }));
+    (QueueSender(Rc::clone(&state)), QueueReceiver(state))
+}
+
+impl<T> QueueSender<T> {
+    pub fn send(&self, value: T) {
+        let mut state = self.0.borrow_mut();
+        state.values.push_back(value);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.0.borrow_mut().senders += 1;
+        QueueSender(Rc::clone(&self.0))
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.0.borrow_mut();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Sink<T> for QueueSender<T> {
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) {
+        self.send(item);
+    }
+}
+
+impl<T> Stream for QueueReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.0.borrow_mut();
+        match state.values.pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if state.senders == 0 => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Queues a task to be polled again, once it is woken
+struct TaskWaker {
+    task: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready
+            .lock()
+            .expect("Executor lock is poisoned")
+            .push_back(self.task);
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Runs tasks cooperatively on the current thread, polling only the ones which were woken
+#[derive(Default)]
+pub struct Executor {
+    // Finished tasks are replaced with `None`
+    tasks: Vec<Option<Task>>,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Executor")
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `task`, which is first polled by the next `run`
+    pub fn spawn<F: Future<Output = ()> + 'static>(&mut self, task: F) {
+        self.ready
+            .lock()
+            .expect("Executor lock is poisoned")
+            .push_back(self.tasks.len());
+        self.tasks.push(Some(Box::pin(task)));
+    }
+
+    /// Runs the tasks, until none of them can continue.
+    /// Returns the number of unfinished tasks, which are all waiting for something,
+    /// which no task will provide.
+    pub fn run(&mut self) -> usize {
+        loop {
+            let next = self
+                .ready
+                .lock()
+                .expect("Executor lock is poisoned")
+                .pop_front();
+            let id = match next {
+                Some(id) => id,
+                None => return self.pending(),
+            };
+            let task = match &mut self.tasks[id] {
+                Some(task) => task,
+                None => continue,
+            };
+            let waker = Waker::from(Arc::new(TaskWaker {
+                task: id,
+                ready: Arc::clone(&self.ready),
+            }));
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                self.tasks[id] = None;
+            }
+        }
+    }
+
+    /// The number of unfinished tasks
+    pub fn pending(&self) -> usize {
+        self.tasks.iter().filter(|task| task.is_some()).count()
+    }
+}
+
+/// Unparks the thread running `block_on`
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on the current thread, sleeping while it waits
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
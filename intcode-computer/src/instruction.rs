@@ -1,22 +1,38 @@
-#[derive(Debug, Clone)]
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     Position,
     Immediate,
     Relative,
 }
 
-impl From<i64> for Mode {
-    fn from(i: i64) -> Self {
+impl TryFrom<i64> for Mode {
+    type Error = i64;
+
+    fn try_from(i: i64) -> Result<Self, Self::Error> {
         match i {
-            0 => Mode::Position,
-            1 => Mode::Immediate,
-            2 => Mode::Relative,
-            _ => unreachable!(),
+            0 => Ok(Mode::Position),
+            1 => Ok(Mode::Immediate),
+            2 => Ok(Mode::Relative),
+            _ => Err(i),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl Mode {
+    /// The digit, which selects the mode in an opcode
+    pub fn code(self) -> i64 {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Opcode {
     ADD,
     MUL,
@@ -30,24 +46,104 @@ pub enum Opcode {
     BRK,
 }
 
-impl From<i64> for Opcode {
-    fn from(i: i64) -> Self {
+impl TryFrom<i64> for Opcode {
+    type Error = i64;
+
+    fn try_from(i: i64) -> Result<Self, Self::Error> {
         match i {
-            1 => Opcode::ADD,
-            2 => Opcode::MUL,
-            3 => Opcode::IN,
-            4 => Opcode::OUT,
-            5 => Opcode::JIF,
-            6 => Opcode::JEQ,
-            7 => Opcode::LT,
-            8 => Opcode::EQ,
-            9 => Opcode::RB,
-            99 => Opcode::BRK,
-            _ => unreachable!("Unexpected opcode: {}", i),
+            1 => Ok(Opcode::ADD),
+            2 => Ok(Opcode::MUL),
+            3 => Ok(Opcode::IN),
+            4 => Ok(Opcode::OUT),
+            5 => Ok(Opcode::JIF),
+            6 => Ok(Opcode::JEQ),
+            7 => Ok(Opcode::LT),
+            8 => Ok(Opcode::EQ),
+            9 => Ok(Opcode::RB),
+            99 => Ok(Opcode::BRK),
+            _ => Err(i),
         }
     }
 }
 
+impl Opcode {
+    /// The number of arguments, which follow the opcode
+    pub fn num_arguments(self) -> usize {
+        match self {
+            Opcode::ADD | Opcode::MUL | Opcode::LT | Opcode::EQ => 3,
+            Opcode::JIF | Opcode::JEQ => 2,
+            Opcode::IN | Opcode::OUT | Opcode::RB => 1,
+            Opcode::BRK => 0,
+        }
+    }
+
+    /// The position of the argument, which is written to (if any)
+    pub fn write_argument(self) -> Option<usize> {
+        match self {
+            Opcode::ADD | Opcode::MUL | Opcode::LT | Opcode::EQ => Some(2),
+            Opcode::IN => Some(0),
+            _ => None,
+        }
+    }
+
+    /// The numeric value of the opcode, without any modes
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::ADD => 1,
+            Opcode::MUL => 2,
+            Opcode::IN => 3,
+            Opcode::OUT => 4,
+            Opcode::JIF => 5,
+            Opcode::JEQ => 6,
+            Opcode::LT => 7,
+            Opcode::EQ => 8,
+            Opcode::RB => 9,
+            Opcode::BRK => 99,
+        }
+    }
+
+    /// Looks up an opcode by its mnemonic, or by its name (e.g. `jnz` or `JIF`), ignoring case.
+    pub fn from_mnemonic(name: &str) -> Option<Self> {
+        let opcode = match name.to_ascii_lowercase().as_str() {
+            "add" => Opcode::ADD,
+            "mul" => Opcode::MUL,
+            "in" => Opcode::IN,
+            "out" => Opcode::OUT,
+            "jnz" | "jif" => Opcode::JIF,
+            "jz" | "jeq" => Opcode::JEQ,
+            "lt" => Opcode::LT,
+            "eq" => Opcode::EQ,
+            "arb" | "rb" => Opcode::RB,
+            "hlt" | "brk" => Opcode::BRK,
+            _ => return None,
+        };
+        Some(opcode)
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::ADD => "add",
+            Opcode::MUL => "mul",
+            Opcode::IN => "in",
+            Opcode::OUT => "out",
+            Opcode::JIF => "jnz",
+            Opcode::JEQ => "jz",
+            Opcode::LT => "lt",
+            Opcode::EQ => "eq",
+            Opcode::RB => "arb",
+            Opcode::BRK => "hlt",
+        }
+    }
+}
+
+/// The reason, why a value could not be decoded into an `Instruction`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode,
+    /// The mode of the argument at the given position is unknown
+    UnknownMode(usize),
+}
+
 #[derive(Debug)]
 pub struct Instruction {
     pub opcode: Opcode,
@@ -55,30 +151,31 @@ pub struct Instruction {
 }
 
 impl Instruction {
-    pub fn new(mut full_opcode: i64) -> Self {
+    pub fn new(mut full_opcode: i64) -> Result<Self, DecodeError> {
         let opcode = full_opcode % 100;
         full_opcode /= 100;
 
         let mut argument_modes = vec![];
 
         while full_opcode > 0 {
-            let mode = (full_opcode % 10).into();
+            let mode = Mode::try_from(full_opcode % 10)
+                .map_err(|_| DecodeError::UnknownMode(argument_modes.len()))?;
             argument_modes.push(mode);
             full_opcode /= 10;
         }
 
-        let opcode = opcode.into();
+        let opcode = Opcode::try_from(opcode).map_err(|_| DecodeError::UnknownOpcode)?;
 
-        Self {
+        Ok(Self {
             opcode,
             argument_modes,
-        }
+        })
     }
 
     pub fn get_mode(&self, index: usize) -> Mode {
         self.argument_modes
             .get(index)
-            .map(Clone::clone)
-            .unwrap_or_else(|| Mode::Position)
+            .copied()
+            .unwrap_or(Mode::Position)
     }
 }
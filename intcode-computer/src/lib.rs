@@ -1,5 +1,49 @@
+mod ascii;
+mod asm;
+mod cfg;
+mod coverage;
+mod debugger;
+mod decompile;
+mod device;
+mod disasm;
+mod error;
+mod executor;
 mod instruction;
 mod machine;
+mod memory;
+mod network;
+mod packet;
+mod predecode;
+mod profile;
+mod program;
+mod smc;
+mod snapshot;
 mod tests;
+mod trace;
+mod undo;
+mod watch;
 
-pub use machine::{run_program, Machine};
+pub use ascii::AsciiMachine;
+pub use asm::{assemble, AsmError};
+pub use cfg::{observed_jumps, BasicBlock, Cfg, Edge, EdgeKind};
+pub use coverage::Coverage;
+pub use debugger::{Debugger, Stop};
+pub use decompile::decompile;
+pub use device::{
+    AsciiInput, AsciiOutput, InputDevice, InputFn, LineInput, OutputDevice, OutputFn,
+};
+pub use disasm::{decode_at, disassemble, DisasmKind, DisasmLine, Operand};
+pub use error::IntcodeError;
+pub use executor::{
+    block_on, queue, Executor, IterStream, QueueReceiver, QueueSender, Sink, Stream,
+};
+pub use instruction::{DecodeError, Instruction, Mode, Opcode};
+pub use machine::{run_program, Effect, Executed, Machine, StepResult};
+pub use network::{Network, NetworkError};
+pub use packet::{Packet, PacketEvent, PacketNetwork};
+pub use profile::Profile;
+pub use program::{ParseError, Program};
+pub use smc::{find_shared_cells, SelfModification, SharedCell, SmcKind};
+pub use snapshot::Snapshot;
+pub use trace::{read_binary_trace, TraceBuffer, TraceFormat, TraceRecord, TraceWriter, Tracer};
+pub use watch::{Access, WatchHit, WatchKind};
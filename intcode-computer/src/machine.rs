@@ -1,50 +1,235 @@
-use crate::instruction::{Instruction, Mode, Opcode::*};
+use crate::device::{InputDevice, OutputDevice};
+use crate::error::IntcodeError;
+use crate::executor::{Sink, Stream};
+use crate::instruction::{DecodeError, Instruction, Mode, Opcode, Opcode::*};
+use crate::memory::Memory;
+use crate::predecode::{DecodeCache, Op};
+use crate::profile::Profile;
+use crate::program::Program;
+use crate::smc::{SelfModification, SmcDetector};
+use crate::snapshot::Snapshot;
+use crate::trace::{TraceRecord, Tracer};
+use crate::undo::{UndoLog, UndoRecord};
+use crate::watch::{Access, WatchHit, WatchKind, Watchpoints};
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::ops::Range;
+use std::pin::Pin;
+use std::time::Instant;
+
+/// The number of instructions between checks of the deadline
+const DEADLINE_INTERVAL: u64 = 1 << 12;
+
+/// The event, which caused a machine to stop running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// An OUT instruction was executed, producing the contained value.
+    Output(i64),
+    /// An IN instruction was reached, while the input queue is empty.
+    NeedsInput,
+    /// The program is done.
+    Halted,
+    /// A watchpoint without a callback was hit.
+    Watchpoint(WatchHit),
+    /// The instruction budget is used up, or the deadline passed.
+    /// The machine continues, once it gets a new budget or deadline.
+    BudgetExhausted,
+}
+
+/// What a single instruction did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// `value` was written to memory at `address`
+    Write { address: usize, value: i64 },
+    /// The value was output
+    Output(i64),
+    /// A jump to `target` was taken
+    Jump { target: usize },
+    /// A jump was not taken
+    NoJump,
+    /// The relative base was changed to the contained value
+    RelativeBase(i64),
+    /// The instruction was not executed, because the input queue is empty
+    NeedsInput,
+    /// The program is done
+    Halted,
+}
+
+impl Effect {
+    /// The event, which stops `run_until_event`, if any
+    pub fn event(self) -> Option<StepResult> {
+        match self {
+            Effect::Output(value) => Some(StepResult::Output(value)),
+            Effect::NeedsInput => Some(StepResult::NeedsInput),
+            Effect::Halted => Some(StepResult::Halted),
+            _ => None,
+        }
+    }
+}
+
+/// A single executed instruction, as reported by `Machine::execute_one`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Executed {
+    /// The address of the instruction
+    pub ip: usize,
+    pub opcode: Opcode,
+    pub effect: Effect,
+}
 
 #[derive(Debug, Default)]
 /// A machine which is capable of running intcode programs.
 /// It takes a program and a vector of input values, as input.
 /// Once done, it returns its memory and a vector of output values (if any).
 pub struct Machine {
-    memory: Vec<i64>,
-    pub input: i64,
+    memory: Memory,
+    input: VecDeque<i64>,
     output: Vec<i64>,
     cur_i: usize,
     relative_base: i64,
     halted: bool,
+    awaiting_input: bool,
+    // Outputs collected by an interrupted `run_until_outputs` call
+    pending_outputs: Vec<i64>,
+    tracer: Option<Box<dyn Tracer + Send>>,
+    input_device: Option<Box<dyn InputDevice + Send>>,
+    output_device: Option<Box<dyn OutputDevice + Send>>,
+    undo: Option<UndoLog>,
+    watchpoints: Watchpoints,
+    cycles: u64,
+    budget: Option<u64>,
+    deadline: Option<Instant>,
+    profile: Option<Profile>,
+    smc: Option<SmcDetector>,
+    decoded: Option<DecodeCache>,
+}
+
+/// The mode and raw value of the arguments of an instruction
+trait Operands {
+    fn mode(&self, arg_position: usize) -> Mode;
+    fn parameter(&self, machine: &Machine, arg_position: usize) -> Result<i64, IntcodeError>;
+}
+
+impl Operands for Instruction {
+    fn mode(&self, arg_position: usize) -> Mode {
+        self.get_mode(arg_position)
+    }
+
+    fn parameter(&self, machine: &Machine, arg_position: usize) -> Result<i64, IntcodeError> {
+        machine.get_parameter(arg_position)
+    }
+}
+
+impl Operands for Op {
+    fn mode(&self, arg_position: usize) -> Mode {
+        self.modes[arg_position]
+    }
+
+    fn parameter(&self, _: &Machine, arg_position: usize) -> Result<i64, IntcodeError> {
+        Ok(self.parameters[arg_position])
+    }
 }
 
 impl Machine {
     pub fn new(program: String, input: i64) -> Self {
-        let mut memory: Vec<i64> = program
-            .trim()
-            .split(',')
-            .collect::<Vec<&str>>()
-            .iter()
-            .map(|opcode| {
-                opcode.parse().unwrap_or_else(|err| {
-                    panic!("Not a number: err={:?} | opcode={:?}", err, opcode);
-                })
-            })
-            .collect();
-        memory.append(&mut vec![0; 10000]);
+        Self::with_inputs(program, vec![input])
+    }
+
+    /// Creates a machine, whose input queue initially holds `inputs`, in order.
+    pub fn with_inputs(program: String, inputs: Vec<i64>) -> Self {
+        let mut machine = Self::try_new(program, 0).unwrap_or_else(|err| panic!("{}", err));
+        machine.input = inputs.into();
+        machine
+    }
+
+    /// Like `new`, but returns an error, if the program is not a list of numbers.
+    pub fn try_new(program: String, input: i64) -> Result<Self, IntcodeError> {
+        let program = Program::parse(&program)?;
+        let mut machine = Self::from_program(&program);
+        machine.push_input(input);
+        Ok(machine)
+    }
+
+    /// Creates a machine running `program`, with an empty input queue.
+    pub fn from_program(program: &Program) -> Self {
         Self {
-            memory,
-            input,
+            memory: Memory::new(program.as_slice().to_vec()),
             ..Default::default()
         }
     }
 
+    /// Creates a machine, in the state saved by `snapshot`.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut machine = Self::default();
+        machine.restore(snapshot);
+        machine
+    }
+
+    /// Takes a copy of the full state of the machine. The tracer and devices are not part of the state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.clone(),
+            ip: self.cur_i,
+            relative_base: self.relative_base,
+            input: self.input.iter().copied().collect(),
+            output: self.output.clone(),
+            pending_outputs: self.pending_outputs.clone(),
+            halted: self.halted,
+            awaiting_input: self.awaiting_input,
+        }
+    }
+
+    /// Returns the machine to the state saved by `snapshot`. The tracer, devices and watchpoints are kept,
+    /// but the undo log and unreported watchpoint hits are cleared.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if let Some(undo) = &mut self.undo {
+            undo.clear();
+        }
+        self.watchpoints.clear_hits();
+        if let Some(decoded) = &mut self.decoded {
+            decoded.clear();
+        }
+        self.memory = snapshot.memory.clone();
+        self.cur_i = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.input = snapshot.input.iter().copied().collect();
+        self.output = snapshot.output.clone();
+        self.pending_outputs = snapshot.pending_outputs.clone();
+        self.halted = snapshot.halted;
+        self.awaiting_input = snapshot.awaiting_input;
+    }
+
+    /// Stores `val` at `idx`, growing the memory if needed.
     pub fn set_memory(&mut self, idx: usize, val: i64) {
-        self.memory[idx] = val;
+        self.write(idx, val);
+    }
+
+    /// Limits the memory to `limit` cells. Accessing a cell beyond the limit is an error.
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory.set_limit(limit);
+        if let Some(decoded) = &mut self.decoded {
+            decoded.clear();
+        }
     }
 
     pub fn is_running(&self) -> bool {
         !self.halted
     }
 
+    /// Returns true, if the machine is blocked on an IN instruction, with an empty input queue.
+    pub fn is_waiting_for_input(&self) -> bool {
+        self.awaiting_input
+    }
+
+    /// Appends `input` to the back of the input queue.
     pub fn push_input(&mut self, input: i64) {
-        //        self.input.push(input);
-        self.input = input;
+        self.input.push_back(input);
+        self.awaiting_input = false;
+    }
+
+    /// Appends every value of `inputs` to the back of the input queue, in order.
+    pub fn extend_input<I: IntoIterator<Item = i64>>(&mut self, inputs: I) {
+        self.input.extend(inputs);
+        self.awaiting_input = self.awaiting_input && self.input.is_empty();
     }
 
     pub fn get_result(&self) -> i64 {
@@ -55,114 +240,767 @@ impl Machine {
         &self.output
     }
 
+    /// The address of the next instruction to execute
+    pub fn get_instruction_pointer(&self) -> usize {
+        self.cur_i
+    }
+
+    pub fn get_relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    /// Attaches `tracer`, which receives every instruction executed from now on.
+    /// To keep access to the tracer, attach an `Arc<Mutex<_>>` of it.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer + Send>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Detaches the tracer, if any
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer + Send>> {
+        self.tracer.take()
+    }
+
+    /// Starts recording the state overwritten by every executed instruction,
+    /// keeping at most the last `capacity` instructions, so they can be reverted by `step_back`.
+    /// Changes made through `set_memory` or `push_input` are not recorded,
+    /// and values written to an output device can not be taken back.
+    pub fn enable_undo(&mut self, capacity: usize) {
+        self.undo = Some(UndoLog::new(capacity));
+    }
+
+    /// Stops recording, and drops the undo log
+    pub fn disable_undo(&mut self) {
+        self.undo = None;
+    }
+
+    /// The number of instructions, which can currently be reverted
+    pub fn undo_depth(&self) -> usize {
+        self.undo.as_ref().map_or(0, UndoLog::len)
+    }
+
+    /// Reverts the most recently executed instruction, restoring the memory cell it wrote,
+    /// the instruction pointer, the relative base, and the input or output it used.
+    /// Returns false, if the undo log is empty or disabled.
+    pub fn step_back(&mut self) -> bool {
+        let record = match self.undo.as_mut().and_then(UndoLog::pop) {
+            Some(record) => record,
+            None => return false,
+        };
+        if let Some((address, value)) = record.write {
+            self.write(address, value);
+        }
+        if let Some(input) = record.input {
+            self.input.push_front(input);
+        }
+        if record.output {
+            self.output.pop();
+        }
+        if record.pending_output {
+            self.pending_outputs.pop();
+        }
+        self.cur_i = record.ip;
+        self.relative_base = record.relative_base;
+        self.halted = false;
+        self.awaiting_input = false;
+        true
+    }
+
+    /// Watches the cells in `range`. Once an instruction accesses one of them as data,
+    /// as selected by `kind`, running the machine stops with `StepResult::Watchpoint`.
+    pub fn add_watchpoint(&mut self, range: Range<usize>, kind: WatchKind) {
+        self.watchpoints.add(range, kind, None);
+    }
+
+    /// Like `add_watchpoint`, but `callback` is called for every hit, instead of stopping.
+    pub fn add_watch_callback<F>(&mut self, range: Range<usize>, kind: WatchKind, callback: F)
+    where
+        F: FnMut(WatchHit) + Send + 'static,
+    {
+        self.watchpoints.add(range, kind, Some(Box::new(callback)));
+    }
+
+    /// The watched ranges, in the order they were added
+    pub fn watchpoints(&self) -> Vec<(Range<usize>, WatchKind)> {
+        self.watchpoints.list()
+    }
+
+    /// Removes all watchpoints, including ones with a callback
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Takes the oldest watchpoint hit, which was not reported yet.
+    /// Only needed when driving the machine through `execute_one`.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watchpoints.take_hit()
+    }
+
+    /// Attaches `device`, which IN instructions read from, once the input queue is empty.
+    /// To keep access to the device, attach an `Arc<Mutex<_>>` of it.
+    pub fn set_input_device(&mut self, device: Box<dyn InputDevice + Send>) {
+        self.input_device = Some(device);
+    }
+
+    /// Detaches the input device, if any
+    pub fn take_input_device(&mut self) -> Option<Box<dyn InputDevice + Send>> {
+        self.input_device.take()
+    }
+
+    /// Attaches `device`, which receives the values of OUT instructions, instead of the output.
+    /// To keep access to the device, attach an `Arc<Mutex<_>>` of it.
+    pub fn set_output_device(&mut self, device: Box<dyn OutputDevice + Send>) {
+        self.output_device = Some(device);
+    }
+
+    /// Detaches the output device, if any. Later values are added to the output again.
+    pub fn take_output_device(&mut self) -> Option<Box<dyn OutputDevice + Send>> {
+        self.output_device.take()
+    }
+
+    /// Limits the number of instructions, which `try_step` and the methods built on it
+    /// execute from now on, or removes the limit.
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
+
+    /// The number of instructions, which can still be executed, if there is a budget
+    pub fn get_budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// Stops `try_step` and the methods built on it, once `deadline` passed.
+    /// The deadline is only checked every few thousand instructions.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// The total number of instructions executed by the machine.
+    /// It is not part of a snapshot, and is not reset by `restore` or `step_back`.
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Starts counting executions per address and opcode, taken and not taken jumps,
+    /// and reads and writes per cell. Replaces the counts collected so far.
+    pub fn enable_profiler(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    /// The counts collected since `enable_profiler`, if the profiler is enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Stops profiling, and returns the collected counts
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Starts or stops reporting writes to cells of executed instructions,
+    /// and executions of instructions with written cells. See `take_self_modification`.
+    pub fn detect_self_modification(&mut self, enabled: bool) {
+        self.smc = if enabled {
+            Some(SmcDetector::default())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the oldest unreported modification of code, if any.
+    /// Every modification is only reported once, even if it happens repeatedly.
+    pub fn take_self_modification(&mut self) -> Option<SelfModification> {
+        self.smc.as_mut().and_then(SmcDetector::take_event)
+    }
+
+    /// Starts or stops executing instructions, which were decoded once, instead of decoding
+    /// them on every execution. Writes to a decoded instruction make it decoded again,
+    /// so self-modifying programs behave the same.
+    /// Filling the cache costs more than decoding an instruction once, so this only pays off,
+    /// if the same instructions are executed many times, as in long running loops.
+    /// Short runs, like the diagnostics of day 5, are slower.
+    pub fn predecode(&mut self, enabled: bool) {
+        self.decoded = if enabled {
+            Some(DecodeCache::default())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the value at `idx`, or `None` if it is beyond the memory limit.
+    pub fn get_memory(&self, idx: usize) -> Option<i64> {
+        self.memory.get(idx)
+    }
+
+    /// The raw opcode at the instruction pointer, used for error reporting
+    fn raw_opcode(&self) -> i64 {
+        self.memory.get(self.cur_i).unwrap_or_default()
+    }
+
+    fn invalid_address(&self, arg_position: usize, address: i64) -> IntcodeError {
+        IntcodeError::InvalidAddress {
+            ip: self.cur_i,
+            opcode: self.raw_opcode(),
+            operand: arg_position,
+            address,
+        }
+    }
+
+    fn overflow(&self) -> IntcodeError {
+        IntcodeError::Overflow {
+            ip: self.cur_i,
+            opcode: self.raw_opcode(),
+        }
+    }
+
+    /// Read the value at `address`
+    fn read(&self, arg_position: usize, address: i64) -> Result<i64, IntcodeError> {
+        if address < 0 {
+            return Err(self.invalid_address(arg_position, address));
+        }
+        self.memory
+            .get(address as usize)
+            .ok_or_else(|| self.invalid_address(arg_position, address))
+    }
+
+    /// Write `val` to `address`, which was previously checked by `get_address`
+    fn write(&mut self, address: usize, val: i64) {
+        self.memory.set(address, val);
+        if let Some(decoded) = &mut self.decoded {
+            decoded.invalidate(address);
+        }
+    }
+
+    /// Get the raw value of the argument at `arg_position`
+    fn get_parameter(&self, arg_position: usize) -> Result<i64, IntcodeError> {
+        let index = (self.cur_i + arg_position + 1) as i64;
+        self.read(arg_position, index)
+    }
+
     /// Get the argument for `instruction`, based on it's mode
-    fn get_argument(&self, instruction: &Instruction, arg_position: usize) -> i64 {
-        let val = self.memory[self.cur_i + arg_position + 1] as usize;
-        match instruction.get_mode(arg_position) {
-            Mode::Position => self.memory[val],
-            Mode::Immediate => val as i64,
-            Mode::Relative => self.memory[(self.relative_base + val as i64) as usize],
+    fn get_argument<O: Operands>(
+        &self,
+        instruction: &O,
+        arg_position: usize,
+    ) -> Result<i64, IntcodeError> {
+        let val = instruction.parameter(self, arg_position)?;
+        match instruction.mode(arg_position) {
+            Mode::Position => self.read(arg_position, val),
+            Mode::Immediate => Ok(val),
+            Mode::Relative => {
+                let address = self
+                    .relative_base
+                    .checked_add(val)
+                    .ok_or_else(|| self.invalid_address(arg_position, val))?;
+                self.read(arg_position, address)
+            }
+        }
+    }
+
+    fn get_address<O: Operands>(
+        &self,
+        instruction: &O,
+        arg_position: usize,
+    ) -> Result<usize, IntcodeError> {
+        let val = instruction.parameter(self, arg_position)?;
+        let address = match instruction.mode(arg_position) {
+            Mode::Position => val,
+            Mode::Relative => self
+                .relative_base
+                .checked_add(val)
+                .ok_or_else(|| self.invalid_address(arg_position, val))?,
+            Mode::Immediate => {
+                return Err(IntcodeError::ImmediateWrite {
+                    ip: self.cur_i,
+                    opcode: self.raw_opcode(),
+                    operand: arg_position,
+                })
+            }
+        };
+        if address < 0 || !self.memory.is_addressable(address as usize) {
+            return Err(self.invalid_address(arg_position, address));
+        }
+        Ok(address as usize)
+    }
+
+    /// Get the jump target for `instruction`
+    fn get_jump_target<O: Operands>(&self, instruction: &O) -> Result<usize, IntcodeError> {
+        let target = self.get_argument(instruction, 1)?;
+        if target < 0 {
+            return Err(self.invalid_address(1, target));
         }
+        Ok(target as usize)
     }
 
-    fn get_address(&self, instruction: &Instruction, arg_position: usize) -> usize {
-        let index = self.cur_i + arg_position + 1;
-        match instruction.get_mode(arg_position) {
-            Mode::Position => self.memory[index] as usize,
-            Mode::Relative => (self.relative_base + self.memory[index]) as usize,
-            Mode::Immediate => unreachable!("Immediate Mode for a write operation: {}", index),
+    /// Take the next value of the input queue, or else of the input device
+    fn read_input(&mut self) -> Option<i64> {
+        match self.input.pop_front() {
+            Some(input) => Some(input),
+            None => self.input_device.as_mut()?.read(),
         }
     }
 
     /// Fetch the next instruction
-    fn fetch_next_instruction(&self) -> Instruction {
-        let opcode = *self.memory.get(self.cur_i).unwrap_or_else(|| {
-            panic!("`self.cur_i` is out of range: {}", self.cur_i);
-        });
-        Instruction::new(opcode)
+    fn fetch_next_instruction(&self) -> Result<Instruction, IntcodeError> {
+        let opcode = self
+            .memory
+            .get(self.cur_i)
+            .ok_or(IntcodeError::InstructionPointerOutOfRange { ip: self.cur_i })?;
+        Instruction::new(opcode).map_err(|err| match err {
+            DecodeError::UnknownOpcode => IntcodeError::UnknownOpcode {
+                ip: self.cur_i,
+                opcode,
+            },
+            DecodeError::UnknownMode(operand) => IntcodeError::UnknownMode {
+                ip: self.cur_i,
+                opcode,
+                operand,
+            },
+        })
+    }
+
+    /// Runs the program, until it is complete or it runs out of input.
+    /// Returns the resulting memory and output.
+    /// Cells written at very high addresses are not part of the returned memory.
+    pub fn run(self) -> (Vec<i64>, Vec<i64>) {
+        self.try_run().unwrap_or_else(|err| panic!("{}", err))
     }
 
-    /// Runs the program, until it is complete. Returns the resulting memory and output.
-    pub fn run(mut self) -> (Vec<i64>, Vec<i64>) {
-        while !self.halted {
-            self.step()
+    /// Like `run`, but returns an error, if the program is faulty.
+    /// Also stops, once the budget is exhausted.
+    pub fn try_run(mut self) -> Result<(Vec<i64>, Vec<i64>), IntcodeError> {
+        while !self.halted && !self.awaiting_input {
+            if self.try_step()? == StepResult::BudgetExhausted {
+                break;
+            }
         }
-        (self.memory, self.output)
+        Ok((self.memory.into_vec(), self.output))
     }
 
-    /// Runs the program, until an OUT instruction is executed or the program is done.
+    /// Runs the program, until an OUT instruction is executed, the program is done,
+    /// or an IN instruction is reached while the input queue is empty.
+    /// In the last case, the IN instruction is retried on the next call.
     pub fn step(&mut self) {
+        self.run_until_event();
+    }
+
+    /// Runs the program, until `n` values are output.
+    /// If the program halts or needs input beforehand, the reason is returned as the error.
+    /// The values output so far are kept, so a later call continues collecting the same batch.
+    pub fn run_until_outputs(&mut self, n: usize) -> Result<Vec<i64>, StepResult> {
+        while self.pending_outputs.len() < n {
+            match self.run_until_event() {
+                StepResult::Output(value) => {
+                    self.pending_outputs.push(value);
+                    if let Some(log) = &mut self.undo {
+                        log.mark_pending_output();
+                    }
+                }
+                event => return Err(event),
+            }
+        }
+        if let Some(log) = &mut self.undo {
+            log.clear_pending_outputs();
+        }
+        Ok(self.pending_outputs.drain(..).collect())
+    }
+
+    /// Runs the program, until an OUT instruction is executed, the program is done,
+    /// or an IN instruction is reached while the input queue is empty.
+    /// The IN instruction is retried, once the machine is resumed.
+    pub fn run_until_event(&mut self) -> StepResult {
+        self.try_step().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `run_until_event`, but returns an error, if the program is faulty.
+    /// The machine is left at the faulting instruction.
+    /// If an instruction both outputs and hits a watchpoint, the output is returned first.
+    pub fn try_step(&mut self) -> Result<StepResult, IntcodeError> {
+        let mut first = true;
+        loop {
+            if let Some(hit) = self.watchpoints.take_hit() {
+                return Ok(StepResult::Watchpoint(hit));
+            }
+            if !self.halted && self.is_out_of_budget(first) {
+                return Ok(StepResult::BudgetExhausted);
+            }
+            first = false;
+            if let Some(event) = self.execute_one()?.effect.event() {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Returns true, if the budget is used up, or the deadline passed.
+    /// The clock is only read on the `first` check, and every `DEADLINE_INTERVAL` instructions.
+    pub(crate) fn is_out_of_budget(&self, first: bool) -> bool {
+        if self.budget == Some(0) {
+            return true;
+        }
+        match self.deadline {
+            Some(deadline) if first || self.cycles.is_multiple_of(DEADLINE_INTERVAL) => {
+                Instant::now() >= deadline
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs the program, reading from `input` whenever the input queue is empty, and sending
+    /// every output to `output`. Waiting for input or for the sink lets other tasks run.
+    /// Finishes with `Halted`, with `BudgetExhausted`, with `Watchpoint` once a watchpoint
+    /// pauses the machine, or with `NeedsInput` once `input` ended, and can be called again
+    /// to resume.
+    pub async fn run_async<I, O>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<StepResult, IntcodeError>
+    where
+        I: Stream<Item = i64> + Unpin,
+        O: Sink<i64> + Unpin,
+    {
         loop {
-            let instruction = self.fetch_next_instruction();
-            match instruction.opcode {
-                ADD => {
-                    let left = self.get_argument(&instruction, 0);
-                    let right = self.get_argument(&instruction, 1);
-                    let dest = self.get_address(&instruction, 2);
-                    self.memory[dest] = left + right;
-                    self.cur_i += 4;
+            let event = self.try_step()?;
+            match event {
+                StepResult::Output(value) => {
+                    poll_fn(|cx| Pin::new(&mut output).poll_ready(cx)).await;
+                    Pin::new(&mut output).start_send(value);
+                }
+                StepResult::NeedsInput => {
+                    match poll_fn(|cx| Pin::new(&mut input).poll_next(cx)).await {
+                        Some(value) => self.push_input(value),
+                        None => return Ok(StepResult::NeedsInput),
+                    }
+                }
+                StepResult::Halted | StepResult::BudgetExhausted | StepResult::Watchpoint(_) => {
+                    return Ok(event)
+                }
+            }
+        }
+    }
+
+    /// Executes a single instruction, and reports what it did.
+    /// The instruction pointer is not advanced, if the instruction needs input, or halts.
+    pub fn execute_one(&mut self) -> Result<Executed, IntcodeError> {
+        let watching = !self.watchpoints.is_empty();
+        let profiling = self.profile.is_some();
+        if self.halted
+            || (self.tracer.is_none()
+                && self.undo.is_none()
+                && self.smc.is_none()
+                && !watching
+                && !profiling)
+        {
+            return self.execute();
+        }
+        let relative_base = self.relative_base;
+        let write = if self.undo.is_some() || watching {
+            self.pending_write()
+        } else {
+            None
+        };
+        let mut reads = if watching || profiling {
+            self.data_reads()
+        } else {
+            vec![]
+        };
+        let operands = match self.tracer {
+            Some(_) => Some(self.resolve_operands()?),
+            None => None,
+        };
+        let executed = self.execute()?;
+        if executed.effect == Effect::NeedsInput {
+            return Ok(executed);
+        }
+        if let Some(log) = &mut self.undo {
+            log.push(UndoRecord {
+                ip: executed.ip,
+                relative_base,
+                write,
+                input: match executed.effect {
+                    Effect::Write { value, .. } if executed.opcode == IN => Some(value),
+                    _ => None,
+                },
+                output: executed.opcode == OUT && self.output_device.is_none(),
+                pending_output: false,
+            });
+        }
+        // The target of a conditional jump is only read, if the jump is taken
+        if matches!(executed.opcode, JIF | JEQ) && executed.effect == Effect::NoJump {
+            reads.retain(|&(position, _, _)| position != 1);
+        }
+        if let Some(profile) = &mut self.profile {
+            let written = match executed.effect {
+                Effect::Write { address, .. } => Some(address),
+                _ => None,
+            };
+            profile.record(executed, reads.iter().map(|&(_, a, _)| a), written);
+        }
+        if watching {
+            self.check_watchpoints(executed, reads, write);
+        }
+        let self_modifying = match &mut self.smc {
+            Some(smc) => smc.check(executed),
+            None => false,
+        };
+        if let Some(operands) = operands {
+            self.trace(executed, operands, self_modifying);
+        }
+        Ok(executed)
+    }
+
+    /// Passes `executed` to the tracer, along with its resolved `operands`
+    fn trace(&mut self, executed: Executed, mut operands: Vec<i64>, self_modifying: bool) {
+        let write = match executed.effect {
+            Effect::Write { address, value } => Some((address, value)),
+            Effect::Jump { target } => {
+                operands.push(target as i64);
+                None
+            }
+            _ => None,
+        };
+        let record = TraceRecord {
+            ip: executed.ip,
+            opcode: executed.opcode,
+            operands,
+            write,
+            relative_base: self.relative_base,
+            self_modifying,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(record);
+        }
+    }
+
+    /// Reports the memory accesses of `executed` to the watchpoints.
+    /// `reads` and `write` were taken before it was executed.
+    fn check_watchpoints(
+        &mut self,
+        executed: Executed,
+        reads: Vec<(usize, usize, i64)>,
+        write: Option<(usize, i64)>,
+    ) {
+        for (_, address, value) in reads {
+            self.watchpoints.check(WatchHit {
+                ip: executed.ip,
+                address,
+                access: Access::Read,
+                old: value,
+                new: value,
+            });
+        }
+        if let (Some((address, old)), Effect::Write { value, .. }) = (write, executed.effect) {
+            self.watchpoints.check(WatchHit {
+                ip: executed.ip,
+                address,
+                access: Access::Write,
+                old,
+                new: value,
+            });
+        }
+    }
+
+    /// The address, which the next instruction writes to, and the value it holds now.
+    /// Nothing is returned for a faulty instruction, since it will not be executed.
+    fn pending_write(&self) -> Option<(usize, i64)> {
+        let instruction = self.fetch_next_instruction().ok()?;
+        let position = instruction.opcode.write_argument()?;
+        let address = self.get_address(&instruction, position).ok()?;
+        Some((address, self.memory.get(address)?))
+    }
+
+    /// The cells, which the next instruction may read as data,
+    /// as tuples of the argument position, the address and the value.
+    fn data_reads(&self) -> Vec<(usize, usize, i64)> {
+        let instruction = match self.fetch_next_instruction() {
+            Ok(instruction) => instruction,
+            Err(_) => return vec![],
+        };
+        let opcode = instruction.opcode;
+        (0..opcode.num_arguments())
+            .filter(|&i| opcode.write_argument() != Some(i))
+            .filter_map(|i| {
+                let val = self.get_parameter(i).ok()?;
+                let address = match instruction.get_mode(i) {
+                    Mode::Position => val,
+                    Mode::Relative => self.relative_base.checked_add(val)?,
+                    Mode::Immediate => return None,
+                };
+                if address < 0 {
+                    return None;
                 }
-                MUL => {
-                    let left = self.get_argument(&instruction, 0);
-                    let right = self.get_argument(&instruction, 1);
-                    let dest = self.get_address(&instruction, 2);
-                    self.memory[dest] = left * right;
-                    self.cur_i += 4;
+                let value = self.memory.get(address as usize)?;
+                Some((i, address as usize, value))
+            })
+            .collect()
+    }
+
+    /// Resolves the arguments of the next instruction, for tracing.
+    /// The target of a conditional jump is left out, since it is only read if the jump is taken.
+    fn resolve_operands(&self) -> Result<Vec<i64>, IntcodeError> {
+        let instruction = self.fetch_next_instruction()?;
+        let opcode = instruction.opcode;
+        let num_arguments = match opcode {
+            JIF | JEQ => 1,
+            _ => opcode.num_arguments(),
+        };
+        (0..num_arguments)
+            .map(|i| {
+                if opcode.write_argument() == Some(i) {
+                    self.get_address(&instruction, i)
+                        .map(|address| address as i64)
+                } else {
+                    self.get_argument(&instruction, i)
                 }
-                IN => {
-                    //                    println!("input_before={:?}", self.input);
-                    //                    let input = self.input.remove(0);
-                    //                    println!("input_after={:?}", self.input);
-                    let dest = self.get_address(&instruction, 0);
-                    self.memory[dest] = self.input;
-                    self.cur_i += 2;
+            })
+            .collect()
+    }
+
+    fn execute(&mut self) -> Result<Executed, IntcodeError> {
+        let ip = self.cur_i;
+        if self.halted {
+            return Ok(Executed {
+                ip,
+                opcode: BRK,
+                effect: Effect::Halted,
+            });
+        }
+        let op = match &mut self.decoded {
+            Some(decoded) => decoded.get(&self.memory, ip),
+            None => None,
+        };
+        let (opcode, effect) = match op {
+            Some(op) => (op.opcode, self.execute_instruction(op.opcode, &op)?),
+            None => {
+                let instruction = self.fetch_next_instruction()?;
+                let effect = self.execute_instruction(instruction.opcode, &instruction)?;
+                (instruction.opcode, effect)
+            }
+        };
+        if effect == Effect::NeedsInput {
+            return Ok(Executed { ip, opcode, effect });
+        }
+        self.cycles += 1;
+        if let Some(budget) = &mut self.budget {
+            *budget = budget.saturating_sub(1);
+        }
+        Ok(Executed { ip, opcode, effect })
+    }
+
+    /// Executes the instruction at the instruction pointer, with the arguments of `instruction`
+    fn execute_instruction<O: Operands>(
+        &mut self,
+        opcode: Opcode,
+        instruction: &O,
+    ) -> Result<Effect, IntcodeError> {
+        let effect = match opcode {
+            ADD => {
+                let left = self.get_argument(instruction, 0)?;
+                let right = self.get_argument(instruction, 1)?;
+                let dest = self.get_address(instruction, 2)?;
+                let res = left.checked_add(right).ok_or_else(|| self.overflow())?;
+                self.write(dest, res);
+                self.cur_i += 4;
+                Effect::Write {
+                    address: dest,
+                    value: res,
                 }
-                OUT => {
-                    let arg = self.get_argument(&instruction, 0);
-                    self.output.push(arg);
-                    self.cur_i += 2;
-                    break;
+            }
+            MUL => {
+                let left = self.get_argument(instruction, 0)?;
+                let right = self.get_argument(instruction, 1)?;
+                let dest = self.get_address(instruction, 2)?;
+                let res = left.checked_mul(right).ok_or_else(|| self.overflow())?;
+                self.write(dest, res);
+                self.cur_i += 4;
+                Effect::Write {
+                    address: dest,
+                    value: res,
                 }
-                JIF => {
-                    let arg = self.get_argument(&instruction, 0);
-                    if arg != 0 {
-                        self.cur_i = self.get_argument(&instruction, 1) as usize;
-                    } else {
-                        self.cur_i += 3;
+            }
+            IN => {
+                let dest = self.get_address(instruction, 0)?;
+                let input = match self.read_input() {
+                    Some(input) => input,
+                    None => {
+                        self.awaiting_input = true;
+                        return Ok(Effect::NeedsInput);
                     }
+                };
+                self.write(dest, input);
+                self.cur_i += 2;
+                Effect::Write {
+                    address: dest,
+                    value: input,
                 }
-                JEQ => {
-                    let arg = self.get_argument(&instruction, 0);
-                    if arg == 0 {
-                        self.cur_i = self.get_argument(&instruction, 1) as usize;
-                    } else {
-                        self.cur_i += 3;
-                    }
+            }
+            OUT => {
+                let arg = self.get_argument(instruction, 0)?;
+                match &mut self.output_device {
+                    Some(device) => device.write(arg),
+                    None => self.output.push(arg),
                 }
-                LT => {
-                    let left = self.get_argument(&instruction, 0);
-                    let right = self.get_argument(&instruction, 1);
-                    let dest = self.get_address(&instruction, 2);
-                    self.memory[dest] = i64::from(left < right);
-                    self.cur_i += 4;
+                self.cur_i += 2;
+                Effect::Output(arg)
+            }
+            JIF => {
+                let arg = self.get_argument(instruction, 0)?;
+                if arg != 0 {
+                    self.cur_i = self.get_jump_target(instruction)?;
+                    Effect::Jump { target: self.cur_i }
+                } else {
+                    self.cur_i += 3;
+                    Effect::NoJump
                 }
-                EQ => {
-                    let left = self.get_argument(&instruction, 0);
-                    let right = self.get_argument(&instruction, 1);
-                    let dest = self.get_address(&instruction, 2);
-                    self.memory[dest] = i64::from(left == right);
-                    self.cur_i += 4;
+            }
+            JEQ => {
+                let arg = self.get_argument(instruction, 0)?;
+                if arg == 0 {
+                    self.cur_i = self.get_jump_target(instruction)?;
+                    Effect::Jump { target: self.cur_i }
+                } else {
+                    self.cur_i += 3;
+                    Effect::NoJump
                 }
-                RB => {
-                    self.relative_base += self.get_argument(&instruction, 0);
-                    self.cur_i += 2;
+            }
+            LT => {
+                let left = self.get_argument(instruction, 0)?;
+                let right = self.get_argument(instruction, 1)?;
+                let dest = self.get_address(instruction, 2)?;
+                let res = i64::from(left < right);
+                self.write(dest, res);
+                self.cur_i += 4;
+                Effect::Write {
+                    address: dest,
+                    value: res,
                 }
-                BRK => {
-                    self.halted = true;
-                    break;
+            }
+            EQ => {
+                let left = self.get_argument(instruction, 0)?;
+                let right = self.get_argument(instruction, 1)?;
+                let dest = self.get_address(instruction, 2)?;
+                let res = i64::from(left == right);
+                self.write(dest, res);
+                self.cur_i += 4;
+                Effect::Write {
+                    address: dest,
+                    value: res,
                 }
             }
-        }
+            RB => {
+                let arg = self.get_argument(instruction, 0)?;
+                self.relative_base = self
+                    .relative_base
+                    .checked_add(arg)
+                    .ok_or_else(|| self.overflow())?;
+                self.cur_i += 2;
+                Effect::RelativeBase(self.relative_base)
+            }
+            BRK => {
+                self.halted = true;
+                Effect::Halted
+            }
+        };
+        Ok(effect)
     }
 }
 
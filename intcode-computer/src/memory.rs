@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+/// Addresses below this are stored contiguously, higher addresses are stored in pages
+const DENSE_LIMIT: usize = 1 << 16;
+const PAGE_SIZE: usize = 1 << 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The memory of a machine, which grows on demand, up to `limit` cells.
+/// Cells, which were never written to, hold 0.
+pub struct Memory {
+    dense: Vec<i64>,
+    pages: HashMap<usize, Vec<i64>>,
+    limit: usize,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            dense: vec![],
+            pages: HashMap::new(),
+            limit: usize::MAX,
+        }
+    }
+}
+
+impl Memory {
+    pub fn new(program: Vec<i64>) -> Self {
+        Self {
+            dense: program,
+            ..Default::default()
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Addresses below this are stored contiguously, which includes the whole program
+    fn dense_len(&self) -> usize {
+        self.dense.len().max(DENSE_LIMIT)
+    }
+
+    /// Set the number of addressable cells. Cells at or above `limit` can not be read or written.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Returns the value at `address`, or `None` if it is not addressable.
+    pub fn get(&self, address: usize) -> Option<i64> {
+        if address >= self.limit {
+            return None;
+        }
+        if address < self.dense_len() {
+            return Some(self.dense.get(address).copied().unwrap_or_default());
+        }
+        let value = self
+            .pages
+            .get(&(address / PAGE_SIZE))
+            .map_or(0, |page| page[address % PAGE_SIZE]);
+        Some(value)
+    }
+
+    /// Returns true, if `address` can be read and written.
+    pub fn is_addressable(&self, address: usize) -> bool {
+        address < self.limit
+    }
+
+    /// Stores `val` at `address`, growing the memory if needed.
+    /// Panics, if `address` is not addressable.
+    pub fn set(&mut self, address: usize, val: i64) {
+        assert!(
+            self.is_addressable(address),
+            "Address {} is beyond the memory limit {}",
+            address,
+            self.limit
+        );
+        if address < self.dense_len() {
+            if address >= self.dense.len() {
+                self.dense.resize(address + 1, 0);
+            }
+            self.dense[address] = val;
+        } else {
+            let page = self
+                .pages
+                .entry(address / PAGE_SIZE)
+                .or_insert_with(|| vec![0; PAGE_SIZE]);
+            page[address % PAGE_SIZE] = val;
+        }
+    }
+
+    /// Returns every stored run of cells, with the address of its first cell, in address order
+    pub fn segments(&self) -> Vec<(usize, &[i64])> {
+        let mut segments = vec![(0, &self.dense[..])];
+        let mut pages: Vec<_> = self
+            .pages
+            .iter()
+            .map(|(page, cells)| (page * PAGE_SIZE, &cells[..]))
+            .collect();
+        pages.sort_by_key(|(address, _)| *address);
+        segments.append(&mut pages);
+        segments
+    }
+
+    /// Returns the contiguous part of the memory, starting at address 0.
+    /// Cells beyond the last written one, and cells stored in pages, are not included.
+    pub fn into_vec(self) -> Vec<i64> {
+        self.dense
+    }
+}
@@ -0,0 +1,306 @@
+use crate::error::IntcodeError;
+use crate::machine::{Machine, StepResult};
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The reason, why a network stopped before every machine halted
+#[derive(Debug)]
+pub enum NetworkError {
+    /// Every machine, which did not halt, is waiting for input, which will never be sent.
+    /// Contains the machines, as they were stopped.
+    Deadlock(Vec<Machine>),
+    /// The program of machine `node` is faulty
+    Fault { node: usize, error: IntcodeError },
+    /// Machine `node` used up its instruction budget, or passed its deadline.
+    /// Contains the machines, as they were stopped, so they can be given a new budget.
+    BudgetExhausted { node: usize, machines: Vec<Machine> },
+    /// Machine `node` of a `PacketNetwork` used up its instruction budget, or passed its
+    /// deadline. The network keeps its machines, and the next round continues where it stopped.
+    Interrupted { node: usize },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Deadlock(machines) => {
+                let waiting: Vec<String> = machines
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, machine)| machine.is_running())
+                    .map(|(node, _)| node.to_string())
+                    .collect();
+                write!(f, "Deadlock: nodes {} wait for input", waiting.join(", "))
+            }
+            NetworkError::Fault { node, error } => write!(f, "Node {} failed: {}", node, error),
+            NetworkError::BudgetExhausted { node, .. } | NetworkError::Interrupted { node } => {
+                write!(f, "Node {} exhausted its budget", node)
+            }
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+#[derive(Debug, Default)]
+/// Machines, whose outputs are sent to the inputs of other machines.
+/// Every output of a machine is sent to all machines it is connected to, and is also kept
+/// in its own output.
+pub struct Network {
+    machines: Vec<Machine>,
+    // The nodes receiving the outputs of each node
+    links: Vec<Vec<usize>>,
+}
+
+impl Network {
+    /// Creates a network of unconnected machines
+    pub fn new(machines: Vec<Machine>) -> Self {
+        let links = vec![vec![]; machines.len()];
+        Self { machines, links }
+    }
+
+    /// Creates a network, where every machine sends its outputs to the next one
+    pub fn pipeline(machines: Vec<Machine>) -> Self {
+        let mut network = Self::new(machines);
+        for node in 1..network.len() {
+            network.connect(node - 1, node);
+        }
+        network
+    }
+
+    /// Creates a pipeline, where the last machine also sends its outputs to the first one
+    pub fn ring(machines: Vec<Machine>) -> Self {
+        let mut network = Self::pipeline(machines);
+        if !network.is_empty() {
+            network.connect(network.len() - 1, 0);
+        }
+        network
+    }
+
+    /// Sends the outputs of machine `from` to machine `to`
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.len(), "No node {} in the network", to);
+        self.links[from].push(to);
+    }
+
+    /// Appends `input` to the input queue of machine `node`
+    pub fn push_input(&mut self, node: usize, input: i64) {
+        self.machines[node].push_input(input);
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    /// Runs the machines one after another on the current thread, each until it needs input
+    /// or halts, until every machine halted. Returns the machines, in the order of the nodes.
+    pub fn run(mut self) -> Result<Vec<Machine>, NetworkError> {
+        loop {
+            for node in 0..self.len() {
+                while self.machines[node].is_running() {
+                    let event = self.machines[node]
+                        .try_step()
+                        .map_err(|error| NetworkError::Fault { node, error })?;
+                    match event {
+                        StepResult::Output(value) => {
+                            for &target in &self.links[node] {
+                                self.machines[target].push_input(value);
+                            }
+                        }
+                        StepResult::NeedsInput => break,
+                        StepResult::Halted | StepResult::Watchpoint(_) => {}
+                        StepResult::BudgetExhausted => {
+                            let machines = self.machines;
+                            return Err(NetworkError::BudgetExhausted { node, machines });
+                        }
+                    }
+                }
+            }
+            // Input sent to a machine clears its waiting state
+            let running = self.machines.iter().filter(|m| m.is_running());
+            if running.clone().all(Machine::is_waiting_for_input) {
+                return match running.count() {
+                    0 => Ok(self.machines),
+                    _ => Err(NetworkError::Deadlock(self.machines)),
+                };
+            }
+        }
+    }
+
+    /// Runs every machine on a thread of its own, connected through channels,
+    /// until every machine halted. Returns the machines, in the order of the nodes.
+    pub fn run_threaded(self) -> Result<Vec<Machine>, NetworkError> {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..self.len()).map(|_| channel()).unzip();
+        let monitor = Arc::new(Monitor::new(senders.clone()));
+        let handles: Vec<_> = self
+            .machines
+            .into_iter()
+            .zip(receivers)
+            .zip(self.links)
+            .enumerate()
+            .map(|(node, ((machine, receiver), links))| {
+                let targets = links
+                    .into_iter()
+                    .map(|target| (target, senders[target].clone()))
+                    .collect();
+                let monitor = Arc::clone(&monitor);
+                thread::spawn(move || run_node(node, machine, receiver, targets, &monitor))
+            })
+            .collect();
+
+        let mut machines = vec![];
+        let mut fault = None;
+        for (node, handle) in handles.into_iter().enumerate() {
+            let (machine, result) = handle.join().expect("Network thread panicked");
+            if let (Err(err), None) = (result, &fault) {
+                fault = Some((node, err));
+            }
+            machines.push(machine);
+        }
+        match fault {
+            Some((node, NodeError::Fault(error))) => Err(NetworkError::Fault { node, error }),
+            Some((node, NodeError::BudgetExhausted)) => {
+                Err(NetworkError::BudgetExhausted { node, machines })
+            }
+            None if monitor.deadlocked() => Err(NetworkError::Deadlock(machines)),
+            None => Ok(machines),
+        }
+    }
+}
+
+/// Why a node of a threaded network stopped, before it halted
+enum NodeError {
+    Fault(IntcodeError),
+    BudgetExhausted,
+}
+
+enum Message {
+    Value(i64),
+    /// The network is deadlocked
+    Stop,
+}
+
+#[derive(Debug)]
+struct NodeState {
+    running: bool,
+    blocked: bool,
+    // Values sent to the node, which it did not receive yet
+    pending: usize,
+}
+
+struct MonitorState {
+    nodes: Vec<NodeState>,
+    deadlocked: bool,
+}
+
+/// Keeps track of the threads of a network, to detect when all of them wait for input
+struct Monitor {
+    state: Mutex<MonitorState>,
+    senders: Vec<Sender<Message>>,
+}
+
+impl Monitor {
+    fn new(senders: Vec<Sender<Message>>) -> Self {
+        let nodes = (0..senders.len())
+            .map(|_| NodeState {
+                running: true,
+                blocked: false,
+                pending: 0,
+            })
+            .collect();
+        Self {
+            state: Mutex::new(MonitorState {
+                nodes,
+                deadlocked: false,
+            }),
+            senders,
+        }
+    }
+
+    fn update<F: FnOnce(&mut NodeState)>(&self, node: usize, f: F) {
+        let mut state = self.state.lock().expect("Network lock is poisoned");
+        f(&mut state.nodes[node]);
+        let nodes = &state.nodes;
+        let waiting = |node: &NodeState| !node.running || (node.blocked && node.pending == 0);
+        if !state.deadlocked && nodes.iter().any(|node| node.running) && nodes.iter().all(waiting) {
+            for (sender, _) in self.senders.iter().zip(nodes).filter(|(_, n)| n.running) {
+                sender.send(Message::Stop).ok();
+            }
+            state.deadlocked = true;
+        }
+    }
+
+    /// Must be called before a value is sent to `node`
+    fn sent(&self, node: usize) {
+        self.update(node, |state| state.pending += 1);
+    }
+
+    fn received(&self, node: usize) {
+        self.update(node, |state| {
+            state.blocked = false;
+            state.pending -= 1;
+        });
+    }
+
+    /// Must be called before `node` waits for a value
+    fn blocked(&self, node: usize) {
+        self.update(node, |state| state.blocked = true);
+    }
+
+    fn finished(&self, node: usize) {
+        self.update(node, |state| state.running = false);
+    }
+
+    fn deadlocked(&self) -> bool {
+        self.state
+            .lock()
+            .expect("Network lock is poisoned")
+            .deadlocked
+    }
+}
+
+/// Runs the machine of `node`, until it halts, fails, exhausts its budget,
+/// or the network is deadlocked
+fn run_node(
+    node: usize,
+    mut machine: Machine,
+    receiver: Receiver<Message>,
+    targets: Vec<(usize, Sender<Message>)>,
+    monitor: &Monitor,
+) -> (Machine, Result<(), NodeError>) {
+    let result = loop {
+        match machine.try_step() {
+            Ok(StepResult::Output(value)) => {
+                for (target, sender) in &targets {
+                    monitor.sent(*target);
+                    sender.send(Message::Value(value)).ok();
+                }
+            }
+            Ok(StepResult::NeedsInput) => {
+                let message = receiver.try_recv().or_else(|_| {
+                    monitor.blocked(node);
+                    receiver.recv()
+                });
+                match message {
+                    Ok(Message::Value(value)) => {
+                        monitor.received(node);
+                        machine.push_input(value);
+                    }
+                    Ok(Message::Stop) | Err(_) => break Ok(()),
+                }
+            }
+            Ok(StepResult::Halted) => break Ok(()),
+            Ok(StepResult::Watchpoint(_)) => {}
+            Ok(StepResult::BudgetExhausted) => break Err(NodeError::BudgetExhausted),
+            Err(error) => break Err(NodeError::Fault(error)),
+        }
+    };
+    monitor.finished(node);
+    (machine, result)
+}
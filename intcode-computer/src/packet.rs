@@ -0,0 +1,213 @@
+use crate::machine::{Machine, StepResult};
+use crate::network::NetworkError;
+use crate::program::Program;
+use std::collections::VecDeque;
+
+/// A packet, sent to the node at `address`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub address: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+/// Something noteworthy, which happened during a round of a packet network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketEvent {
+    /// A node sent a packet to the NAT
+    ToNat(Packet),
+    /// The network was idle, so the NAT sent its last packet to its target node
+    FromNat(Packet),
+    /// A packet was sent to an address without a node, and was dropped
+    Dropped(Packet),
+}
+
+/// A round, which stopped, because a node exhausted its budget
+#[derive(Debug)]
+struct Interruption {
+    node: usize,
+    // The events of the round, before it stopped
+    events: Vec<PacketEvent>,
+    idle: bool,
+}
+
+/// A special node, which keeps the last packet sent to it, and sends it on
+/// once the network is idle
+#[derive(Debug)]
+struct Nat {
+    address: i64,
+    target: usize,
+    last: Option<Packet>,
+}
+
+#[derive(Debug, Default)]
+/// Machines, which send each other packets, as triples of an address, `x` and `y`.
+/// Every machine gets its address as its first input, and reads queued packets as `x` and `y`,
+/// or `-1` if there is none. The machines are run in turns, in the order of their addresses,
+/// so runs are deterministic.
+pub struct PacketNetwork {
+    machines: Vec<Machine>,
+    queues: Vec<VecDeque<(i64, i64)>>,
+    // The values of the incomplete packet of each node
+    partial: Vec<Vec<i64>>,
+    nat: Option<Nat>,
+    idle: bool,
+    interrupted: Option<Interruption>,
+}
+
+impl PacketNetwork {
+    /// Boots `size` machines running `program`
+    pub fn new(program: &Program, size: usize) -> Self {
+        Self::from_machines((0..size).map(|_| Machine::from_program(program)).collect())
+    }
+
+    /// Boots `machines`, giving each one its index as its address
+    pub fn from_machines(mut machines: Vec<Machine>) -> Self {
+        for (address, machine) in machines.iter_mut().enumerate() {
+            machine.push_input(address as i64);
+        }
+        Self {
+            queues: vec![VecDeque::new(); machines.len()],
+            partial: vec![vec![]; machines.len()],
+            machines,
+            nat: None,
+            idle: false,
+            interrupted: None,
+        }
+    }
+
+    /// Adds a NAT at `address`, which sends its last packet to node `target`,
+    /// whenever the network is idle.
+    pub fn set_nat(&mut self, address: i64, target: usize) {
+        assert!(target < self.machines.len(), "No node {}", target);
+        self.nat = Some(Nat {
+            address,
+            target,
+            last: None,
+        });
+    }
+
+    pub fn machines(&self) -> &[Machine] {
+        &self.machines
+    }
+
+    /// The machines, e.g. to give them a new budget, once a round stopped on it
+    pub fn machines_mut(&mut self) -> &mut [Machine] {
+        &mut self.machines
+    }
+
+    /// Returns true, if no packets were sent in the last round, and no node had any to read.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Returns true, if any machine did not halt yet
+    pub fn is_running(&self) -> bool {
+        self.machines.iter().any(Machine::is_running)
+    }
+
+    /// Gives every machine a turn, in which it reads its queued packets, or `-1`,
+    /// and runs until it needs more input. If no packets were sent, and there were none to read,
+    /// the NAT sends its last packet. Returns what happened, in order.
+    /// If a machine exhausts its budget, the round stops there. The next call continues
+    /// the round with that machine, where it stopped, and also returns the events
+    /// from before the interruption.
+    pub fn round(&mut self) -> Result<Vec<PacketEvent>, NetworkError> {
+        let (resumed, mut events, mut idle) = match self.interrupted.take() {
+            Some(Interruption { node, events, idle }) => (Some(node), events, idle),
+            None => (None, vec![], true),
+        };
+        for node in resumed.unwrap_or(0)..self.machines.len() {
+            if !self.machines[node].is_running() {
+                continue;
+            }
+            // An interrupted node continues its turn, with the input it already got
+            if resumed != Some(node) {
+                let machine = &mut self.machines[node];
+                if self.queues[node].is_empty() {
+                    machine.push_input(-1);
+                } else {
+                    idle = false;
+                    for (x, y) in self.queues[node].drain(..) {
+                        machine.extend_input([x, y]);
+                    }
+                }
+            }
+            loop {
+                let event = self.machines[node]
+                    .try_step()
+                    .map_err(|error| NetworkError::Fault { node, error })?;
+                match event {
+                    StepResult::Output(value) => {
+                        self.partial[node].push(value);
+                        if self.partial[node].len() == 3 {
+                            let packet = Packet {
+                                address: self.partial[node][0],
+                                x: self.partial[node][1],
+                                y: self.partial[node][2],
+                            };
+                            self.partial[node].clear();
+                            idle = false;
+                            events.extend(self.route(packet));
+                        }
+                    }
+                    StepResult::NeedsInput | StepResult::Halted => break,
+                    StepResult::Watchpoint(_) => {}
+                    StepResult::BudgetExhausted => {
+                        self.interrupted = Some(Interruption { node, events, idle });
+                        return Err(NetworkError::Interrupted { node });
+                    }
+                }
+            }
+        }
+        self.idle = idle;
+        if let (true, Some(nat)) = (idle, &mut self.nat) {
+            if let Some(packet) = nat.last {
+                let packet = Packet {
+                    address: nat.target as i64,
+                    ..packet
+                };
+                self.queues[nat.target].push_back((packet.x, packet.y));
+                events.push(PacketEvent::FromNat(packet));
+            }
+        }
+        Ok(events)
+    }
+
+    /// Runs rounds, until `stop` returns true for an event, and returns that event.
+    /// Returns `None`, once every machine halted, or once the network is idle,
+    /// and the NAT has nothing to send.
+    pub fn run_until<F>(&mut self, mut stop: F) -> Result<Option<PacketEvent>, NetworkError>
+    where
+        F: FnMut(&PacketEvent) -> bool,
+    {
+        while self.is_running() {
+            let events = self.round()?;
+            if let Some(&event) = events.iter().find(|event| stop(event)) {
+                return Ok(Some(event));
+            }
+            if self.idle && events.is_empty() {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Queues `packet` for its node, or passes it to the NAT
+    fn route(&mut self, packet: Packet) -> Option<PacketEvent> {
+        match &mut self.nat {
+            Some(nat) if nat.address == packet.address => {
+                nat.last = Some(packet);
+                return Some(PacketEvent::ToNat(packet));
+            }
+            _ => {}
+        }
+        match self.queues.get_mut(packet.address as usize) {
+            Some(queue) if packet.address >= 0 => {
+                queue.push_back((packet.x, packet.y));
+                None
+            }
+            _ => Some(PacketEvent::Dropped(packet)),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::instruction::{Instruction, Mode, Opcode};
+use crate::memory::Memory;
+
+/// Instructions at or above this address are not cached, but decoded on every execution
+const CACHE_LIMIT: usize = 1 << 16;
+
+/// The largest number of cells an instruction covers
+const MAX_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An instruction, decoded along with the raw values of its arguments
+pub(crate) struct Op {
+    pub opcode: Opcode,
+    pub modes: [Mode; 3],
+    pub parameters: [i64; 3],
+}
+
+impl Op {
+    /// Decodes the instruction at `address`. Returns `None`, if it is faulty, or any of its cells
+    /// can not be read, so that the interpreter reports the error.
+    fn decode(memory: &Memory, address: usize) -> Option<Self> {
+        let instruction = Instruction::new(memory.get(address)?).ok()?;
+        let mut op = Op {
+            opcode: instruction.opcode,
+            modes: [Mode::Position; 3],
+            parameters: [0; 3],
+        };
+        for i in 0..instruction.opcode.num_arguments() {
+            op.modes[i] = instruction.get_mode(i);
+            op.parameters[i] = memory.get(address + i + 1)?;
+        }
+        Some(op)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// The instructions of a program, decoded the first time they are executed.
+/// Writing to a cell drops the instructions covering it, so self-modifying code
+/// is decoded again.
+pub(crate) struct DecodeCache {
+    ops: Vec<Option<Op>>,
+}
+
+impl DecodeCache {
+    /// Returns the instruction at `address`, decoding it if needed
+    pub fn get(&mut self, memory: &Memory, address: usize) -> Option<Op> {
+        if let Some(Some(op)) = self.ops.get(address) {
+            return Some(*op);
+        }
+        let op = Op::decode(memory, address)?;
+        if address < CACHE_LIMIT {
+            if address >= self.ops.len() {
+                self.ops.resize(address + 1, None);
+            }
+            self.ops[address] = Some(op);
+        }
+        Some(op)
+    }
+
+    /// Drops every instruction, which covers the cell at `address`
+    pub fn invalidate(&mut self, address: usize) {
+        let start = address.saturating_sub(MAX_SIZE - 1);
+        let end = (address + 1).min(self.ops.len());
+        for op in self.ops.iter_mut().take(end).skip(start) {
+            *op = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
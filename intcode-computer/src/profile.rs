@@ -0,0 +1,156 @@
+use crate::coverage::Coverage;
+use crate::disasm::{disassemble, DisasmKind};
+use crate::instruction::Opcode;
+use crate::machine::{Effect, Executed};
+use crate::program::Program;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// The number of addresses, listed as hot spots by `write_report`
+const HOT_SPOTS: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Execution counts, collected by a machine with an enabled profiler.
+/// See `Machine::enable_profiler`.
+pub struct Profile {
+    executions: HashMap<usize, u64>,
+    opcodes: HashMap<Opcode, u64>,
+    // Taken and not taken counts of conditional jumps
+    branches: HashMap<usize, (u64, u64)>,
+    reads: HashMap<usize, u64>,
+    writes: HashMap<usize, u64>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `executed`, which read the cells at `reads`, and wrote the one at `write`
+    pub(crate) fn record<I>(&mut self, executed: Executed, reads: I, write: Option<usize>)
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        *self.executions.entry(executed.ip).or_default() += 1;
+        *self.opcodes.entry(executed.opcode).or_default() += 1;
+        match executed.effect {
+            Effect::Jump { .. } => self.branches.entry(executed.ip).or_default().0 += 1,
+            Effect::NoJump => self.branches.entry(executed.ip).or_default().1 += 1,
+            _ => {}
+        }
+        for address in reads {
+            *self.reads.entry(address).or_default() += 1;
+        }
+        if let Some(address) = write {
+            *self.writes.entry(address).or_default() += 1;
+        }
+    }
+
+    /// The total number of executed instructions
+    pub fn total(&self) -> u64 {
+        self.executions.values().sum()
+    }
+
+    /// The number of times, the instruction at `address` was executed
+    pub fn executions(&self, address: usize) -> u64 {
+        self.executions.get(&address).copied().unwrap_or_default()
+    }
+
+    /// The number of executed instructions with `opcode`
+    pub fn opcode_count(&self, opcode: Opcode) -> u64 {
+        self.opcodes.get(&opcode).copied().unwrap_or_default()
+    }
+
+    /// The number of times, the conditional jump at `address` was taken, and not taken
+    pub fn branch(&self, address: usize) -> (u64, u64) {
+        self.branches.get(&address).copied().unwrap_or_default()
+    }
+
+    /// The number of times, the cell at `address` was read as data
+    pub fn reads(&self, address: usize) -> u64 {
+        self.reads.get(&address).copied().unwrap_or_default()
+    }
+
+    /// The number of times, the cell at `address` was written
+    pub fn writes(&self, address: usize) -> u64 {
+        self.writes.get(&address).copied().unwrap_or_default()
+    }
+
+    /// The `count` most executed addresses, with their execution counts, most executed first
+    pub fn hot_spots(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut hot_spots: Vec<(usize, u64)> =
+            self.executions.iter().map(|(&a, &c)| (a, c)).collect();
+        hot_spots.sort_by_key(|&(address, count)| (std::cmp::Reverse(count), address));
+        hot_spots.truncate(count);
+        hot_spots
+    }
+
+    /// The addresses, which were executed, and the cells, which were read or written,
+    /// as the coverage of a single run
+    pub fn coverage(&self) -> Coverage {
+        Coverage::from_run(
+            self.executions.iter().map(|(&a, &c)| (a, c)).collect(),
+            self.branches.iter().map(|(&a, &b)| (a, b)).collect(),
+            self.reads
+                .keys()
+                .chain(self.writes.keys())
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// Writes the disassembly of `program`, with the execution count of every instruction,
+    /// followed by the counts per opcode, and the hottest addresses.
+    pub fn write_report<W: Write>(&self, program: &Program, mut writer: W) -> io::Result<()> {
+        for line in disassemble(program) {
+            let executions = self.executions(line.address);
+            let mut notes = vec![];
+            if let DisasmKind::Instruction {
+                opcode: Opcode::JIF | Opcode::JEQ,
+                ..
+            } = line.kind
+            {
+                if executions > 0 {
+                    let (taken, not_taken) = self.branch(line.address);
+                    notes.push(format!("taken {}, not taken {}", taken, not_taken));
+                }
+            }
+            let cells = line.address..line.address + line.size();
+            let reads: u64 = cells.clone().map(|address| self.reads(address)).sum();
+            let writes: u64 = cells.map(|address| self.writes(address)).sum();
+            if reads > 0 {
+                notes.push(format!("reads {}", reads));
+            }
+            if writes > 0 {
+                notes.push(format!("writes {}", writes));
+            }
+            let count = match executions {
+                0 => String::new(),
+                count => count.to_string(),
+            };
+            let notes = if notes.is_empty() {
+                String::new()
+            } else {
+                format!("  ; {}", notes.join(", "))
+            };
+            writeln!(
+                writer,
+                "{:>10} {:>6}: {}{}",
+                count, line.address, line, notes
+            )?;
+        }
+
+        writeln!(writer, "\nInstructions by opcode:")?;
+        let mut opcodes: Vec<(&Opcode, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by_key(|&(opcode, &count)| (std::cmp::Reverse(count), opcode.code()));
+        for (opcode, count) in opcodes {
+            writeln!(writer, "{:>10} {}", count, opcode.mnemonic())?;
+        }
+
+        writeln!(writer, "\nHottest addresses:")?;
+        for (address, count) in self.hot_spots(HOT_SPOTS) {
+            writeln!(writer, "{:>10} {:>6}", count, address)?;
+        }
+        writeln!(writer, "\nTotal: {} instructions", self.total())
+    }
+}
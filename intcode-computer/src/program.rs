@@ -0,0 +1,90 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A value of the program text, which is not a number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line of the token
+    pub line: usize,
+    /// 1-based column of the first character of the token
+    pub column: usize,
+    pub token: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Not a number at line {}, column {}: {:?}",
+            self.line, self.column, self.token
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A parsed intcode program, which can be loaded into any number of machines.
+/// Cloning a program does not copy its values.
+pub struct Program {
+    code: Arc<[i64]>,
+}
+
+impl Program {
+    /// Parses a program, made up of numbers, separated by commas and/or whitespace.
+    /// Everything from a `#` to the end of the line is a comment.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut code = vec![];
+        for (line_i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut start = None;
+            // A trailing separator terminates the last token of the line
+            for (column, c) in line.char_indices().chain(Some((line.len(), ','))) {
+                let is_separator = c == ',' || c.is_whitespace();
+                match (start, is_separator) {
+                    (None, false) => start = Some(column),
+                    (Some(token_start), true) => {
+                        let token = &line[token_start..column];
+                        let value = token.parse().map_err(|_| ParseError {
+                            line: line_i + 1,
+                            column: line[..token_start].chars().count() + 1,
+                            token: String::from(token),
+                        })?;
+                        code.push(value);
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(Self { code: code.into() })
+    }
+
+    pub fn as_slice(&self) -> &[i64] {
+        &self.code
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl From<Vec<i64>> for Program {
+    fn from(code: Vec<i64>) -> Self {
+        Self { code: code.into() }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cfg::Cfg;
+use crate::disasm::DisasmKind;
+use crate::instruction::Mode;
+use crate::machine::{Effect, Executed};
+use crate::watch::Access;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+
+/// How code and data met
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SmcKind {
+    /// A cell of an executed instruction was written afterwards
+    WroteCode,
+    /// An instruction was executed, after one of its cells was written
+    ExecutedData,
+}
+
+/// A write to code, or an execution of written data, reported by a machine,
+/// which detects self-modifying code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SelfModification {
+    /// The address of the writing, or of the executed instruction
+    pub ip: usize,
+    /// The cell, which is both code and data
+    pub address: usize,
+    pub kind: SmcKind,
+}
+
+impl fmt::Display for SelfModification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            SmcKind::WroteCode => write!(f, "write to executed [{}]", self.address),
+            SmcKind::ExecutedData => write!(f, "execution of written [{}]", self.address),
+        }?;
+        write!(f, " at {}", self.ip)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Remembers the cells, which were executed and written, to detect self-modifying code
+pub(crate) struct SmcDetector {
+    executed: HashSet<usize>,
+    written: HashSet<usize>,
+    // Every modification is only reported once
+    reported: HashSet<SelfModification>,
+    events: VecDeque<SelfModification>,
+}
+
+impl SmcDetector {
+    /// Checks the cells, which `executed` covered and wrote.
+    /// Returns true, if it modified code, or was modified itself.
+    pub fn check(&mut self, executed: Executed) -> bool {
+        let mut found = vec![];
+        let size = executed.opcode.num_arguments() + 1;
+        for address in executed.ip..executed.ip + size {
+            if self.written.contains(&address) {
+                found.push((address, SmcKind::ExecutedData));
+            }
+            self.executed.insert(address);
+        }
+        if let Effect::Write { address, .. } = executed.effect {
+            if self.executed.contains(&address) {
+                found.push((address, SmcKind::WroteCode));
+            }
+            self.written.insert(address);
+        }
+        for &(address, kind) in &found {
+            let event = SelfModification {
+                ip: executed.ip,
+                address,
+                kind,
+            };
+            if self.reported.insert(event) {
+                self.events.push_back(event);
+            }
+        }
+        !found.is_empty()
+    }
+
+    pub fn take_event(&mut self) -> Option<SelfModification> {
+        self.events.pop_front()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A cell of a reachable instruction, which is also an operand of a reachable instruction
+pub struct SharedCell {
+    pub address: usize,
+    /// The address of the instruction, which the cell is part of
+    pub instruction: usize,
+    /// The instructions accessing the cell as data, with the kind of access
+    pub accesses: Vec<(usize, Access)>,
+}
+
+/// Finds the cells, which are used as both code and data by the instructions of `cfg`.
+/// Only operands in position mode are considered, since relative addresses are only known
+/// at runtime.
+pub fn find_shared_cells(cfg: &Cfg) -> Vec<SharedCell> {
+    let mut code = BTreeMap::new();
+    let mut data: BTreeMap<usize, Vec<(usize, Access)>> = BTreeMap::new();
+    for line in cfg.blocks().flat_map(|block| &block.lines) {
+        let (opcode, operands) = match &line.kind {
+            DisasmKind::Instruction { opcode, operands } => (opcode, operands),
+            DisasmKind::Data(_) => continue,
+        };
+        for address in line.address..line.address + line.size() {
+            code.entry(address).or_insert(line.address);
+        }
+        for (i, operand) in operands.iter().enumerate() {
+            if operand.mode != Mode::Position || operand.value < 0 {
+                continue;
+            }
+            let access = match opcode.write_argument() {
+                Some(write) if write == i => Access::Write,
+                _ => Access::Read,
+            };
+            let accesses = data.entry(operand.value as usize).or_default();
+            if !accesses.contains(&(line.address, access)) {
+                accesses.push((line.address, access));
+            }
+        }
+    }
+    data.into_iter()
+        .filter_map(|(address, accesses)| {
+            Some(SharedCell {
+                address,
+                instruction: *code.get(&address)?,
+                accesses,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,150 @@
+use crate::memory::Memory;
+use crate::program::Program;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// The first line of every saved snapshot, which also identifies the format version
+const HEADER: &str = "intcode-snapshot 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The full state of a machine, taken by `Machine::snapshot`.
+///
+/// A snapshot is saved as text: the header line `intcode-snapshot 1`, followed by one
+/// `key value` line per field. Lists of numbers are comma separated, and memory is
+/// saved as `memory ADDRESS VALUES` lines, one per run of cells starting at `ADDRESS`.
+pub struct Snapshot {
+    pub(crate) memory: Memory,
+    pub(crate) ip: usize,
+    pub(crate) relative_base: i64,
+    pub(crate) input: Vec<i64>,
+    pub(crate) output: Vec<i64>,
+    pub(crate) pending_outputs: Vec<i64>,
+    pub(crate) halted: bool,
+    pub(crate) awaiting_input: bool,
+}
+
+/// Writes a `key VALUES` line, or just `key` if there are no values
+fn write_list<W: Write>(writer: &mut W, key: &str, values: &[i64]) -> io::Result<()> {
+    let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+    if values.is_empty() {
+        writeln!(writer, "{}", key)
+    } else {
+        writeln!(writer, "{} {}", key, values.join(","))
+    }
+}
+
+fn invalid_data(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid snapshot, line {}: {}", line, message),
+    )
+}
+
+impl Snapshot {
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(writer, "ip {}", self.ip)?;
+        writeln!(writer, "rb {}", self.relative_base)?;
+        writeln!(writer, "halted {}", i64::from(self.halted))?;
+        writeln!(writer, "waiting {}", i64::from(self.awaiting_input))?;
+        write_list(&mut writer, "input", &self.input)?;
+        write_list(&mut writer, "output", &self.output)?;
+        write_list(&mut writer, "pending", &self.pending_outputs)?;
+        if self.memory.limit() != usize::MAX {
+            writeln!(writer, "limit {}", self.memory.limit())?;
+        }
+        // Cells beyond the limit can not be accessed, so they are not saved
+        let limit = self.memory.limit();
+        for (address, cells) in self.memory.segments() {
+            if address < limit {
+                let cells = &cells[..cells.len().min(limit - address)];
+                write_list(&mut writer, &format!("memory {}", address), cells)?;
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn read_from<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid_data(1, "missing header"));
+        }
+        let mut snapshot = Snapshot {
+            memory: Memory::default(),
+            ip: 0,
+            relative_base: 0,
+            input: vec![],
+            output: vec![],
+            pending_outputs: vec![],
+            halted: false,
+            awaiting_input: false,
+        };
+        // The end of the highest run of cells so far
+        let mut written = 0;
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let line_number = i + 2;
+            let error = |message: &str| invalid_data(line_number, message);
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default().trim();
+            let number = |s: &str| s.parse::<i64>().map_err(|_| error("not a number"));
+            let address =
+                |s: &str| usize::try_from(number(s)?).map_err(|_| error("negative address"));
+            let list = |s: &str| -> io::Result<Vec<i64>> {
+                s.split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(&number)
+                    .collect()
+            };
+            match key {
+                "ip" => snapshot.ip = address(value)?,
+                "rb" => snapshot.relative_base = number(value)?,
+                "halted" => snapshot.halted = number(value)? != 0,
+                "waiting" => snapshot.awaiting_input = number(value)? != 0,
+                "input" => snapshot.input = list(value)?,
+                "output" => snapshot.output = list(value)?,
+                "pending" => snapshot.pending_outputs = list(value)?,
+                "limit" => {
+                    let limit = address(value)?;
+                    if written > limit {
+                        return Err(error("memory beyond the limit"));
+                    }
+                    snapshot.memory.set_limit(limit);
+                }
+                "memory" => {
+                    let mut parts = value.splitn(2, ' ');
+                    let start = address(parts.next().unwrap_or_default())?;
+                    let cells = list(parts.next().unwrap_or_default())?;
+                    let end = start
+                        .checked_add(cells.len())
+                        .filter(|&end| end <= snapshot.memory.limit())
+                        .ok_or_else(|| error("memory beyond the limit"))?;
+                    for (offset, &cell) in cells.iter().enumerate() {
+                        snapshot.memory.set(start + offset, cell);
+                    }
+                    written = written.max(end);
+                }
+                "" => {}
+                _ => return Err(error(&format!("unknown key {:?}", key))),
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// The contiguous part of the memory, starting at address 0, as a program,
+    /// e.g. to annotate the profile of a resumed machine
+    pub fn program(&self) -> Program {
+        Program::from(self.memory.clone().into_vec())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
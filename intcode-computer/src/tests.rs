@@ -1,7 +1,20 @@
 #![allow(unused_imports)]
 #![allow(dead_code)]
 
-use crate::run_program;
+use crate::{
+    assemble, block_on, decode_at, decompile, disassemble, find_shared_cells, observed_jumps,
+    queue, read_binary_trace, run_program, Access, AsciiInput, AsciiMachine, AsciiOutput, AsmError,
+    Cfg, Coverage, Debugger, DisasmKind, Edge, EdgeKind, Effect, Executed, Executor, InputDevice,
+    InputFn, IntcodeError, IterStream, LineInput, Machine, Mode, Network, NetworkError, Opcode,
+    Operand, OutputDevice, OutputFn, Packet, PacketEvent, PacketNetwork, ParseError, Program,
+    SelfModification, SharedCell, SmcKind, Snapshot, StepResult, TraceBuffer, TraceFormat,
+    TraceRecord, TraceWriter, WatchHit, WatchKind,
+};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[test]
 fn test1() {
@@ -222,8 +235,1692 @@ fn additional_test8() {
     run_test("109,7,204,0,99,-10,-11,-12", -12);
 }
 
+#[test]
+fn test_input_queue_order() {
+    // Reads two values and outputs their difference
+    let program = String::from("3,15,3,16,1002,16,-1,16,1,15,16,15,4,15,99");
+    let machine = Machine::with_inputs(program, vec![10, 3]);
+    let (_, output) = machine.run();
+    assert_eq!(output, vec![7]);
+}
+
+#[test]
+fn test_waiting_for_input() {
+    let program = String::from("3,15,3,16,1002,16,-1,16,1,15,16,15,4,15,99");
+    let mut machine = Machine::with_inputs(program, vec![10]);
+    machine.step();
+    assert!(machine.is_running());
+    assert!(machine.is_waiting_for_input());
+    assert!(machine.get_output().is_empty());
+
+    // Stepping again, without any new input, must not consume stale data
+    machine.step();
+    assert!(machine.is_waiting_for_input());
+
+    machine.push_input(4);
+    assert!(!machine.is_waiting_for_input());
+    machine.step();
+    assert_eq!(machine.get_output(), &vec![6]);
+}
+
+#[test]
+fn test_extend_input() {
+    let program = String::from("3,15,3,16,1002,16,-1,16,1,15,16,15,4,15,99");
+    let mut machine = Machine::with_inputs(program, vec![]);
+    machine.step();
+    assert!(machine.is_waiting_for_input());
+    machine.extend_input(vec![1, 5]);
+    machine.step();
+    assert_eq!(machine.get_result(), -4);
+}
+
+#[test]
+fn test_run_until_event() {
+    let program = String::from("3,15,3,16,1002,16,-1,16,1,15,16,15,4,15,99");
+    let mut machine = Machine::with_inputs(program, vec![10]);
+    assert_eq!(machine.run_until_event(), StepResult::NeedsInput);
+    machine.push_input(4);
+    assert_eq!(machine.run_until_event(), StepResult::Output(6));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+    assert!(!machine.is_running());
+}
+
+#[test]
+fn test_run_until_outputs() {
+    // Outputs the input, then 7, then the input again
+    let program = String::from("3,11,4,11,104,7,3,11,4,11,99,0");
+    let mut machine = Machine::with_inputs(program, vec![1]);
+    assert_eq!(machine.run_until_outputs(3), Err(StepResult::NeedsInput));
+    machine.push_input(2);
+    assert_eq!(machine.run_until_outputs(3), Ok(vec![1, 7, 2]));
+    assert_eq!(machine.run_until_outputs(3), Err(StepResult::Halted));
+}
+
+#[test]
+fn test_error_not_a_number() {
+    let err = Machine::try_new(String::from("1,0,x,0,99"), 0).unwrap_err();
+    assert_eq!(
+        err,
+        IntcodeError::Parse(ParseError {
+            line: 1,
+            column: 5,
+            token: String::from("x")
+        })
+    );
+}
+
+#[test]
+fn test_error_unknown_opcode() {
+    let machine = Machine::try_new(String::from("1101,1,1,5,42"), 0).unwrap();
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(err, IntcodeError::UnknownOpcode { ip: 4, opcode: 42 });
+}
+
+#[test]
+fn test_error_unknown_mode() {
+    let mut machine = Machine::try_new(String::from("304,0,99"), 0).unwrap();
+    let err = machine.try_step().unwrap_err();
+    assert_eq!(
+        err,
+        IntcodeError::UnknownMode {
+            ip: 0,
+            opcode: 304,
+            operand: 0
+        }
+    );
+}
+
+#[test]
+fn test_error_immediate_write() {
+    let machine = Machine::try_new(String::from("11101,1,1,5,99"), 0).unwrap();
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(
+        err,
+        IntcodeError::ImmediateWrite {
+            ip: 0,
+            opcode: 11101,
+            operand: 2
+        }
+    );
+}
+
+#[test]
+fn test_error_negative_address() {
+    let machine = Machine::try_new(String::from("4,-3,99"), 0).unwrap();
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(
+        err,
+        IntcodeError::InvalidAddress {
+            ip: 0,
+            opcode: 4,
+            operand: 0,
+            address: -3
+        }
+    );
+}
+
+#[test]
+fn test_error_instruction_pointer_out_of_range() {
+    let mut machine = Machine::try_new(String::from("1106,0,100000"), 0).unwrap();
+    machine.set_memory_limit(1000);
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(
+        err,
+        IntcodeError::InstructionPointerOutOfRange { ip: 100_000 }
+    );
+}
+
+#[test]
+fn test_memory_grows_on_demand() {
+    // Stores the input at a high address and outputs it back
+    let program = String::from("3,1000000,4,1000000,99");
+    let (memory, output) = run_program(program, 42);
+    assert_eq!(output, vec![42]);
+    assert_eq!(memory, vec![3, 1_000_000, 4, 1_000_000, 99]);
+
+    let program = String::from("3,20,4,20,99");
+    let (memory, _) = run_program(program, 42);
+    assert_eq!(memory.len(), 21);
+    assert_eq!(memory[20], 42);
+}
+
+#[test]
+fn test_unwritten_memory_is_zero() {
+    run_test("4,123456789,99", 0);
+}
+
+#[test]
+fn test_memory_limit() {
+    let mut machine = Machine::try_new(String::from("3,1000,99"), 0).unwrap();
+    machine.set_memory_limit(100);
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(
+        err,
+        IntcodeError::InvalidAddress {
+            ip: 0,
+            opcode: 3,
+            operand: 0,
+            address: 1000
+        }
+    );
+}
+
+#[test]
+fn test_set_memory_grows() {
+    let mut machine = Machine::try_new(String::from("4,50,99"), 0).unwrap();
+    machine.set_memory(50, 7);
+    machine.step();
+    assert_eq!(machine.get_result(), 7);
+}
+
+#[test]
+fn test_program_parse() {
+    let text = "# Outputs the input\n3, 5,\n  4,5 # the result\n\n99\n";
+    let program = Program::parse(text).unwrap();
+    assert_eq!(program.as_slice(), &[3, 5, 4, 5, 99]);
+}
+
+#[test]
+fn test_program_parse_error() {
+    let err = "1,2,3\n4, 5x,6".parse::<Program>().unwrap_err();
+    assert_eq!(
+        err,
+        ParseError {
+            line: 2,
+            column: 4,
+            token: String::from("5x")
+        }
+    );
+}
+
+#[test]
+fn test_from_program() {
+    let program = Program::parse("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+    for (input, expected) in &[(8, 1), (7, 0)] {
+        let mut machine = Machine::from_program(&program);
+        machine.push_input(*input);
+        let (_, output) = machine.run();
+        assert_eq!(output, vec![*expected]);
+    }
+    assert_eq!(program.len(), 11);
+}
+
+#[test]
+fn test_disassemble() {
+    let program =
+        Program::parse("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99").unwrap();
+    let lines: Vec<String> = disassemble(&program)
+        .iter()
+        .map(|line| format!("{}: {}", line.address, line))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            "0: arb #1",
+            "2: out rb-1",
+            "4: add [100], #1, [100]",
+            "8: eq [100], #16, [101]",
+            "12: jz [101], #0",
+            "15: hlt",
+        ]
+    );
+    assert_eq!(
+        decode_at(program.as_slice(), 15).unwrap().to_string(),
+        "hlt"
+    );
+    assert_eq!(decode_at(program.as_slice(), 16), None);
+}
+
+#[test]
+fn test_disassemble_operands() {
+    let program = Program::parse("22201,3,-4,5").unwrap();
+    let lines = disassemble(&program);
+    assert_eq!(
+        lines[0].kind,
+        DisasmKind::Instruction {
+            opcode: Opcode::ADD,
+            operands: vec![
+                Operand {
+                    mode: Mode::Relative,
+                    value: 3
+                },
+                Operand {
+                    mode: Mode::Relative,
+                    value: -4
+                },
+                Operand {
+                    mode: Mode::Relative,
+                    value: 5
+                },
+            ]
+        }
+    );
+    assert_eq!(lines[0].to_string(), "add rb+3, rb-4, rb+5");
+}
+
+#[test]
+fn test_disassemble_data_fallback() {
+    // Unknown opcode, unknown mode, immediate write, and cut off arguments
+    let program = Program::parse("42,304,0,11101,1,1,5,1,2").unwrap();
+    let lines: Vec<String> = disassemble(&program)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            "data 42",
+            "data 304",
+            "data 0",
+            "data 11101",
+            "add [1], [5], [1]",
+            "data 2",
+        ]
+    );
+}
+
+// The programs used by the tests above
+const TEST_PROGRAMS: &[&str] = &[
+    "3,0,4,0,99",
+    "1101,100,-1,0,99",
+    "3,9,8,9,10,9,4,9,99,-1,8",
+    "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9",
+    "3,3,1108,-1,8,3,4,3,99",
+    "3,9,7,9,10,9,4,9,99,-1,8",
+    "3,3,1107,-1,8,3,4,3,99",
+    "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+    "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+    "1102,34915192,34915192,7,4,7,99,0",
+    "104,1125899906842624,99",
+    "109,-1,104,1,99",
+    "109,-1,204,1,99",
+    "109,5,104,0,99,-10,-11,-12",
+    "109,5,204,0,99,-10,-11,-12",
+    "3,15,3,16,1002,16,-1,16,1,15,16,15,4,15,99",
+    "3,11,4,11,104,7,3,11,4,11,99,0",
+    "1101,1,1,5,42",
+    "304,0,99",
+    "11101,1,1,5,99",
+];
+
+#[test]
+fn test_assemble() {
+    let text = "
+        ; Outputs the numbers from 3 down to 1
+                in [counter]
+        loop:   out [counter]
+                add [counter], #-1, [counter]
+                jnz [counter], #loop
+                hlt
+        counter: db 0
+    ";
+    let program = assemble(text).unwrap();
+    assert_eq!(
+        program.as_slice(),
+        &[3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]
+    );
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(3);
+    let (_, output) = machine.run();
+    assert_eq!(output, vec![3, 2, 1]);
+}
+
+#[test]
+fn test_assemble_modes_and_labels() {
+    let text = "start: arb #3\nOUT rb-1\njif #1, [start+1]\ndb 7, start, end\nend: BRK";
+    let program = assemble(text).unwrap();
+    assert_eq!(
+        program.as_slice(),
+        &[109, 3, 204, -1, 105, 1, 1, 7, 0, 10, 99]
+    );
+}
+
+#[test]
+fn test_assemble_errors() {
+    let err = assemble("add [1], [2]").unwrap_err();
+    assert_eq!(err.line, 1);
+    let err = assemble("hlt\nin #5").unwrap_err();
+    assert_eq!(err.line, 2);
+    let err = assemble("jz [1], #nowhere").unwrap_err();
+    assert_eq!(
+        err,
+        AsmError {
+            line: 1,
+            message: String::from("Unknown label: \"nowhere\"")
+        }
+    );
+    let err = assemble("x: db 1\nx: db 2").unwrap_err();
+    assert_eq!(err.line, 2);
+    assert!(assemble("foo [1]").is_err());
+}
+
+#[test]
+fn test_disassemble_assemble_round_trip() {
+    for text in TEST_PROGRAMS {
+        let program = Program::parse(text).unwrap();
+        let listing: Vec<String> = disassemble(&program)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let assembled = assemble(&listing.join("\n")).unwrap();
+        assert_eq!(assembled, program, "Round trip failed for {}", text);
+    }
+}
+
+#[test]
+fn test_execute_one() {
+    let program = "109,3,3,14,1005,14,9,104,0,1007,14,5,14,99";
+    let mut machine = Machine::try_new(String::from(program), 4).unwrap();
+    let mut effects = vec![];
+    while machine.is_running() {
+        let executed = machine.execute_one().unwrap();
+        effects.push((executed.ip, executed.effect));
+    }
+    assert_eq!(
+        effects,
+        vec![
+            (0, Effect::RelativeBase(3)),
+            (
+                2,
+                Effect::Write {
+                    address: 14,
+                    value: 4
+                }
+            ),
+            (4, Effect::Jump { target: 9 }),
+            (
+                9,
+                Effect::Write {
+                    address: 14,
+                    value: 1
+                }
+            ),
+            (13, Effect::Halted),
+        ]
+    );
+    assert_eq!(machine.get_relative_base(), 3);
+    assert_eq!(machine.get_instruction_pointer(), 13);
+}
+
+#[test]
+fn test_execute_one_events() {
+    let mut machine = Machine::with_inputs(String::from("1006,5,0,104,7,0"), vec![]);
+    machine.set_memory(5, 1);
+    assert_eq!(machine.execute_one().unwrap().effect, Effect::NoJump);
+    assert_eq!(
+        machine.execute_one().unwrap(),
+        Executed {
+            ip: 3,
+            opcode: Opcode::OUT,
+            effect: Effect::Output(7)
+        }
+    );
+    assert_eq!(machine.get_output(), &vec![7]);
+
+    let mut machine = Machine::with_inputs(String::from("3,0,99"), vec![]);
+    assert_eq!(machine.execute_one().unwrap().effect, Effect::NeedsInput);
+    assert_eq!(machine.get_instruction_pointer(), 0);
+    assert!(machine.is_waiting_for_input());
+}
+
+fn run_traced(program: &str, input: i64, capacity: usize) -> TraceBuffer {
+    let buffer = Arc::new(Mutex::new(TraceBuffer::new(capacity)));
+    let mut machine = Machine::try_new(String::from(program), input).unwrap();
+    machine.set_tracer(Box::new(buffer.clone()));
+    machine.run();
+    let buffer = buffer.lock().unwrap();
+    buffer.clone()
+}
+
+#[test]
+fn test_trace_records() {
+    let buffer = run_traced("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9", 5, 100);
+    let records: Vec<&TraceRecord> = buffer.records().collect();
+    assert_eq!(records.len(), 5);
+    assert_eq!(
+        *records[0],
+        TraceRecord {
+            ip: 0,
+            opcode: Opcode::IN,
+            operands: vec![12],
+            write: Some((12, 5)),
+            relative_base: 0,
+            self_modifying: false
+        }
+    );
+    // The jump is not taken, so its target is not part of the operands
+    assert_eq!(records[1].operands, vec![5]);
+    assert_eq!(records[2].operands, vec![0, 1, 13]);
+    assert_eq!(records[3].operands, vec![1]);
+    assert_eq!(records[4].opcode, Opcode::BRK);
+}
+
+#[test]
+fn test_trace_ring_buffer() {
+    let buffer = run_traced("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9", 0, 2);
+    let ips: Vec<usize> = buffer.records().map(|record| record.ip).collect();
+    assert_eq!(ips, vec![9, 11]);
+}
+
+#[test]
+fn test_trace_json_lines() {
+    let buffer = run_traced("109,-1,204,1,1106,0,8,0,99", 0, 10);
+    let mut json = vec![];
+    buffer.write_to(&mut json, TraceFormat::JsonLines).unwrap();
+    let expected = r#"{"ip":0,"op":"arb","operands":[-1],"write":null,"rb":-1}
+{"ip":2,"op":"out","operands":[109],"write":null,"rb":-1}
+{"ip":4,"op":"jz","operands":[0,8],"write":null,"rb":-1}
+{"ip":8,"op":"hlt","operands":[],"write":null,"rb":-1}
+"#;
+    assert_eq!(String::from_utf8(json).unwrap(), expected);
+}
+
+#[test]
+fn test_trace_binary_round_trip() {
+    let program = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+    let buffer = run_traced(program, 0, 1000);
+    let mut binary = vec![];
+    buffer.write_to(&mut binary, TraceFormat::Binary).unwrap();
+    let records = read_binary_trace(&binary[..]).unwrap();
+    assert_eq!(records, buffer.records().cloned().collect::<Vec<_>>());
+    assert_eq!(records.len(), 81);
+    assert!(read_binary_trace(&binary[..binary.len() - 1]).is_err());
+}
+
+#[test]
+fn test_trace_writer() {
+    let mut machine = Machine::try_new(String::from("1101,2,3,5,99"), 0).unwrap();
+    let writer = Arc::new(Mutex::new(TraceWriter::new(vec![], TraceFormat::JsonLines)));
+    machine.set_tracer(Box::new(writer.clone()));
+    machine.run();
+    let writer = Arc::try_unwrap(writer).unwrap().into_inner().unwrap();
+    let json = String::from_utf8(writer.finish().unwrap()).unwrap();
+    assert_eq!(
+        json,
+        "{\"ip\":0,\"op\":\"add\",\"operands\":[2,3,5],\"write\":[5,5],\"rb\":0}\n\
+         {\"ip\":4,\"op\":\"hlt\",\"operands\":[],\"write\":null,\"rb\":0}\n"
+    );
+}
+
+#[test]
+fn test_snapshot_restore() {
+    // Outputs the sum of two inputs
+    let program = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(5);
+    assert_eq!(machine.run_until_event(), StepResult::NeedsInput);
+    let snapshot = machine.snapshot();
+
+    machine.push_input(1);
+    assert_eq!(machine.run_until_event(), StepResult::Output(6));
+
+    // Roll back, and try a different input
+    machine.restore(&snapshot);
+    assert!(machine.is_waiting_for_input());
+    assert!(machine.get_output().is_empty());
+    machine.push_input(10);
+    assert_eq!(machine.run_until_event(), StepResult::Output(15));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+}
+
+#[test]
+fn test_snapshot_file_format() {
+    let mut machine = Machine::with_inputs(String::from("109,7,204,-3,3,1000000,99"), vec![]);
+    machine.set_memory(200_000, -5);
+    assert_eq!(machine.run_until_event(), StepResult::Output(3));
+    machine.extend_input(vec![8, 9]);
+    let snapshot = machine.snapshot();
+
+    let mut text = vec![];
+    snapshot.write_to(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines[..8],
+        [
+            "intcode-snapshot 1",
+            "ip 4",
+            "rb 7",
+            "halted 0",
+            "waiting 0",
+            "input 8,9",
+            "output 3",
+            "pending"
+        ]
+    );
+    assert_eq!(lines[8], "memory 0 109,7,204,-3,3,1000000,99");
+    assert!(lines[9].starts_with("memory 199680 0,0,"));
+    assert_eq!(lines.len(), 10);
+
+    let loaded = Snapshot::read_from(text.as_bytes()).unwrap();
+    assert_eq!(loaded, snapshot);
+    assert_eq!(
+        loaded.program().as_slice(),
+        [109, 7, 204, -3, 3, 1000000, 99]
+    );
+
+    let mut resumed = Machine::from_snapshot(&loaded);
+    assert_eq!(resumed.run_until_event(), StepResult::Halted);
+    assert_eq!(resumed.get_memory(1_000_000), Some(8));
+    assert_eq!(resumed.get_memory(200_000), Some(-5));
+}
+
+#[test]
+fn test_snapshot_invalid() {
+    assert!(Snapshot::read_from("ip 4\n".as_bytes()).is_err());
+    assert!(Snapshot::read_from("intcode-snapshot 1\nip x\n".as_bytes()).is_err());
+    assert!(Snapshot::read_from("intcode-snapshot 1\nfoo 1\n".as_bytes()).is_err());
+    for line in &["ip -1", "memory -1 5", "memory 18446744073709551615 1,2"] {
+        let text = format!("intcode-snapshot 1\n{}\n", line);
+        assert!(Snapshot::read_from(text.as_bytes()).is_err(), "{}", line);
+    }
+    let text = "intcode-snapshot 1\nlimit 2\nmemory 0 1,2,3\n";
+    assert!(Snapshot::read_from(text.as_bytes()).is_err());
+    let text = "intcode-snapshot 1\nmemory 0 1,2,3\nlimit 2\n";
+    assert!(Snapshot::read_from(text.as_bytes()).is_err());
+}
+
+#[test]
+fn test_snapshot_memory_limit() {
+    // Outputs the cell at 7, with a limit, which cuts off the cells after it
+    let program = Program::parse("1101,2,3,7,4,7,99,0,5,5,5").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.set_memory_limit(8);
+    let mut text = vec![];
+    machine.snapshot().write_to(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.ends_with("limit 8\nmemory 0 1101,2,3,7,4,7,99,0\n"));
+
+    let mut resumed = Machine::from_snapshot(&Snapshot::read_from(text.as_bytes()).unwrap());
+    assert_eq!(resumed.get_memory(8), None);
+    assert_eq!(resumed.run_until_event(), StepResult::Output(5));
+    assert_eq!(resumed.run_until_event(), StepResult::Halted);
+}
+
+#[test]
+fn test_step_back() {
+    // Outputs the numbers from the input down to 1
+    let program = Program::parse("3,12,4,12,1001,12,-1,12,1005,12,2,99,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    assert!(!machine.step_back());
+    machine.enable_undo(100);
+    machine.push_input(2);
+    assert_eq!(machine.run_until_event(), StepResult::Output(2));
+    assert_eq!(machine.run_until_event(), StepResult::Output(1));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+    assert_eq!(machine.undo_depth(), 8);
+
+    // Un-halt, and revert the last output
+    assert!(machine.step_back());
+    assert!(machine.is_running());
+    assert_eq!(machine.get_instruction_pointer(), 11);
+    for _ in 0..3 {
+        assert!(machine.step_back());
+    }
+    assert_eq!(machine.get_instruction_pointer(), 2);
+    assert_eq!(machine.get_memory(12), Some(1));
+    assert_eq!(machine.get_output(), &vec![2]);
+
+    // Back to the start, with the input returned to the queue
+    while machine.step_back() {}
+    assert_eq!(machine.get_instruction_pointer(), 0);
+    assert_eq!(machine.get_memory(12), Some(0));
+    assert!(machine.get_output().is_empty());
+    assert_eq!(machine.run_until_outputs(2), Ok(vec![2, 1]));
+
+    // Outputs every input. Reverting an output only takes it back from an interrupted batch,
+    // if it was collected there.
+    let mut machine = Machine::with_inputs(String::from("3,7,4,7,1105,1,0,0"), vec![5]);
+    machine.enable_undo(100);
+    assert_eq!(machine.run_until_outputs(2), Err(StepResult::NeedsInput));
+    machine.push_input(6);
+    assert_eq!(machine.run_until_event(), StepResult::Output(6));
+    assert!(machine.step_back());
+    assert_eq!(machine.get_output(), &vec![5]);
+    assert_eq!(machine.run_until_outputs(2), Ok(vec![5, 6]));
+}
+
+#[test]
+fn test_step_back_relative_base() {
+    let mut machine = Machine::with_inputs(String::from("109,3,204,2,99,7"), vec![]);
+    machine.enable_undo(2);
+    assert_eq!(machine.run_until_event(), StepResult::Output(7));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+    // Only the last two instructions are kept
+    assert_eq!(machine.undo_depth(), 2);
+    assert!(machine.step_back());
+    assert!(machine.step_back());
+    assert!(!machine.step_back());
+    assert_eq!(machine.get_instruction_pointer(), 2);
+    assert_eq!(machine.get_relative_base(), 3);
+
+    machine.restore(&Machine::with_inputs(String::from("99"), vec![]).snapshot());
+    assert_eq!(machine.undo_depth(), 0);
+}
+
+#[test]
+fn test_watchpoint_pauses() {
+    // Outputs the numbers from the input down to 1
+    let program = Program::parse("3,12,4,12,1001,12,-1,12,1005,12,2,99,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(2);
+    machine.add_watchpoint(12..13, WatchKind::Write);
+    let write = |ip, old, new| {
+        StepResult::Watchpoint(WatchHit {
+            ip,
+            address: 12,
+            access: Access::Write,
+            old,
+            new,
+        })
+    };
+    assert_eq!(machine.run_until_event(), write(0, 0, 2));
+    assert_eq!(machine.run_until_event(), StepResult::Output(2));
+    assert_eq!(machine.run_until_event(), write(4, 2, 1));
+    // Collecting outputs is resumed after the watchpoint
+    assert_eq!(machine.run_until_outputs(2), Err(write(4, 1, 0)));
+    assert_eq!(machine.run_until_outputs(2), Err(StepResult::Halted));
+    assert_eq!(machine.get_output(), &vec![2, 1]);
+}
+
+#[test]
+fn test_watchpoint_callback() {
+    let program = Program::parse("3,12,4,12,1001,12,-1,12,1005,12,2,99,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(2);
+    let hits = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&hits);
+    machine.add_watch_callback(10..13, WatchKind::ReadWrite, move |hit| {
+        sink.lock()
+            .unwrap()
+            .push((hit.ip, hit.access, hit.old, hit.new))
+    });
+    assert_eq!(machine.watchpoints(), vec![(10..13, WatchKind::ReadWrite)]);
+    // Callbacks do not stop the machine
+    assert_eq!(machine.run_until_event(), StepResult::Output(2));
+    assert_eq!(machine.run_until_event(), StepResult::Output(1));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+    let (r, w) = (Access::Read, Access::Write);
+    assert_eq!(
+        *hits.lock().unwrap(),
+        vec![
+            (0, w, 0, 2),
+            (2, r, 2, 2),
+            (4, r, 2, 2),
+            (4, w, 2, 1),
+            (8, r, 1, 1),
+            (2, r, 1, 1),
+            (4, r, 1, 1),
+            (4, w, 1, 0),
+            (8, r, 0, 0),
+        ]
+    );
+
+    machine.clear_watchpoints();
+    assert!(machine.watchpoints().is_empty());
+}
+
+#[test]
+fn test_devices() {
+    // Outputs the sum of two inputs
+    let program = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    let queue = Arc::new(Mutex::new(VecDeque::new()));
+    let output = Arc::new(Mutex::new(vec![]));
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(5);
+    machine.set_input_device(Box::new(Arc::clone(&queue)));
+    machine.set_output_device(Box::new(Arc::clone(&output)));
+
+    // The input queue is used first, then the device
+    assert_eq!(machine.run_until_event(), StepResult::NeedsInput);
+    queue.lock().unwrap().push_back(3);
+    assert_eq!(machine.run_until_event(), StepResult::Output(8));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+    assert_eq!(*output.lock().unwrap(), vec![8]);
+    assert!(machine.get_output().is_empty());
+    assert!(machine.take_output_device().is_some());
+}
+
+#[test]
+fn test_closure_devices() {
+    // Outputs the numbers from the input down to 1
+    let program = Program::parse("3,12,4,12,1001,12,-1,12,1005,12,2,99,0").unwrap();
+    let mut inputs = vec![3];
+    let outputs = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&outputs);
+    let mut machine = Machine::from_program(&program);
+    machine.set_input_device(Box::new(InputFn(move || inputs.pop())));
+    machine.set_output_device(Box::new(OutputFn(move |value| {
+        sink.lock().unwrap().push(value)
+    })));
+    machine.try_run().unwrap();
+    assert_eq!(*outputs.lock().unwrap(), vec![3, 2, 1]);
+}
+
+#[test]
+fn test_channel_devices() {
+    let program = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    let (input, receiver) = std::sync::mpsc::channel();
+    let (sender, output) = std::sync::mpsc::channel();
+    let mut machine = Machine::from_program(&program);
+    machine.set_input_device(Box::new(receiver));
+    machine.set_output_device(Box::new(sender));
+    let handle = std::thread::spawn(move || machine.run());
+    input.send(20).unwrap();
+    input.send(22).unwrap();
+    assert_eq!(output.recv(), Ok(42));
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_text_devices() {
+    let program = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.set_input_device(Box::new(LineInput::new("x\n  \n7, -2 5\n".as_bytes())));
+    assert_eq!(machine.run_until_event(), StepResult::Output(5));
+
+    // Echoes the input, with 1000 after the first character
+    let program = Program::parse("3,9,4,9,104,1000,1105,1,0,0").unwrap();
+    let ascii = Arc::new(Mutex::new(AsciiOutput::new(vec![])));
+    let mut machine = Machine::from_program(&program);
+    machine.set_input_device(Box::new(AsciiInput::new("hi")));
+    machine.set_output_device(Box::new(Arc::clone(&ascii)));
+    assert_eq!(machine.try_step(), Ok(StepResult::Output(104)));
+    assert_eq!(machine.try_step(), Ok(StepResult::Output(1000)));
+    while machine.try_step() != Ok(StepResult::NeedsInput) {}
+    drop(machine);
+    let ascii = Arc::try_unwrap(ascii).unwrap().into_inner().unwrap();
+    assert_eq!(ascii.finish().unwrap(), b"h1000\ni1000\n");
+}
+
+/// Echoes every line in upper case, followed by the result 1000
+const SHOUT: &str = "
+loop:   in [c]
+        eq [c], #10, [t]
+        jnz [t], #done
+        add [c], #-32, [c]
+        out [c]
+        jz #0, #loop
+done:   out #10
+        out #1000
+        jz #0, #loop
+c:      db 0
+t:      db 0
+";
+
+#[test]
+fn test_ascii_machine() {
+    let program = assemble(SHOUT).unwrap();
+    let mut machine = AsciiMachine::new(Machine::from_program(&program));
+    assert_eq!(machine.run(), Ok(String::new()));
+    machine.send_line("hello");
+    machine.send_line("abc");
+    assert_eq!(machine.run(), Ok(String::from("HELLO\nABC\n")));
+    assert_eq!(machine.take_results(), vec![1000, 1000]);
+    assert!(machine.take_results().is_empty());
+    assert!(machine.machine().is_waiting_for_input());
+    assert!(!machine.is_out_of_budget());
+
+    // The machine stops in the middle of the line, once the budget is used up
+    let mut machine = Machine::from_program(&program);
+    machine.set_budget(Some(20));
+    let mut machine = AsciiMachine::new(machine);
+    assert_eq!(machine.run(), Ok(String::new()));
+    machine.send_line("hello");
+    let text = machine.run().unwrap();
+    assert!(machine.is_out_of_budget());
+    assert!(text.len() < 6);
+    assert_eq!(machine.run(), Ok(String::new()));
+    assert!(machine.is_out_of_budget());
+    machine.machine_mut().set_budget(None);
+    let rest = machine.run().unwrap();
+    assert!(!machine.is_out_of_budget());
+    assert_eq!(text + &rest, "HELLO\n");
+}
+
+/// The amplifiers of the day 7 feedback loop example, with the phases 9,8,7,6,5
+fn amplifiers() -> Vec<Machine> {
+    let program = Program::parse(
+        "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+    )
+    .unwrap();
+    (5..10)
+        .rev()
+        .map(|phase| {
+            let mut machine = Machine::from_program(&program);
+            machine.push_input(phase);
+            machine
+        })
+        .collect()
+}
+
+#[test]
+fn test_network_ring() {
+    let mut network = Network::ring(amplifiers());
+    network.push_input(0, 0);
+    let machines = network.run().unwrap();
+    assert_eq!(machines[4].get_result(), 139_629_729);
+
+    let mut network = Network::ring(amplifiers());
+    network.push_input(0, 0);
+    let machines = network.run_threaded().unwrap();
+    assert_eq!(machines[4].get_result(), 139_629_729);
+}
+
+#[test]
+fn test_network_graph() {
+    // Outputs the sum of two inputs
+    let adder = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    // Outputs twice its input
+    let doubler = Program::parse("3,9,1002,9,2,9,4,9,99").unwrap();
+    let machines = vec![
+        Machine::from_program(&doubler),
+        Machine::from_program(&doubler),
+        Machine::from_program(&adder),
+    ];
+    // The first doubler feeds both the second one and the adder
+    let mut network = Network::new(machines);
+    network.connect(0, 1);
+    network.connect(0, 2);
+    network.connect(1, 2);
+    network.push_input(0, 5);
+    let machines = network.run_threaded().unwrap();
+    assert_eq!(machines[2].get_output(), &vec![30]);
+}
+
+#[test]
+fn test_network_deadlock() {
+    let program = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    let machines = || {
+        vec![
+            Machine::from_program(&program),
+            Machine::from_program(&program),
+        ]
+    };
+    // Each adder waits for the second input, which only the other one could send
+    let mut network = Network::ring(machines());
+    network.push_input(0, 1);
+    network.push_input(1, 2);
+    match network.run() {
+        Err(NetworkError::Deadlock(machines)) => {
+            assert!(machines.iter().all(Machine::is_waiting_for_input))
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+
+    let mut network = Network::ring(machines());
+    network.push_input(0, 1);
+    let err = network.run_threaded().unwrap_err();
+    assert_eq!(err.to_string(), "Deadlock: nodes 0, 1 wait for input");
+
+    let network = Network::pipeline(vec![Machine::from_program(&Program::from(vec![1, 0]))]);
+    match network.run_threaded() {
+        Err(NetworkError::Fault { node: 0, .. }) => {}
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+/// Node 0 sends a packet to node 1 on boot. Every node forwards each packet it reads to the
+/// next address, with `x` incremented.
+const FORWARDER: &str = "
+        in [addr]
+        jnz [addr], #loop
+        out #1
+        out #0
+        out #100
+loop:   in [x]
+        eq [x], #-1, [t]
+        jnz [t], #loop
+        in [y]
+        add [addr], #1, [t]
+        out [t]
+        add [x], #1, [x]
+        out [x]
+        out [y]
+        jz #0, #loop
+addr:   db 0
+x:      db 0
+y:      db 0
+t:      db 0
+";
+
+#[test]
+fn test_packet_network() {
+    let program = assemble(FORWARDER).unwrap();
+    let packet = |address, x| Packet { address, x, y: 100 };
+
+    // Without a NAT, the packet leaves the network. Nodes take turns in order,
+    // so it passes every node in the first round.
+    let mut network = PacketNetwork::new(&program, 3);
+    let events = network.round().unwrap();
+    assert_eq!(events, vec![PacketEvent::Dropped(packet(3, 2))]);
+    assert!(!network.is_idle());
+    assert_eq!(network.round().unwrap(), vec![]);
+    assert!(network.is_idle());
+    assert_eq!(network.run_until(|_| true).unwrap(), None);
+    assert!(network.machines().iter().all(Machine::is_waiting_for_input));
+
+    // A node, which exhausts its budget, continues where it stopped in the next call,
+    // which also returns the events from before the interruption
+    let mut network = PacketNetwork::new(&program, 3);
+    network.machines_mut()[1].set_budget(Some(3));
+    match network.round() {
+        Err(NetworkError::Interrupted { node: 1 }) => {}
+        result => panic!("Unexpected result: {:?}", result),
+    }
+    network.machines_mut()[1].set_budget(None);
+    let events = network.round().unwrap();
+    assert_eq!(events, vec![PacketEvent::Dropped(packet(3, 2))]);
+
+    // The NAT sends the packet around again, once the network is idle
+    let mut network = PacketNetwork::new(&program, 3);
+    network.set_nat(3, 0);
+    let mut events = vec![];
+    let last = network.run_until(|event| {
+        events.push(*event);
+        matches!(event, PacketEvent::FromNat(Packet { x: 5, .. }))
+    });
+    assert_eq!(last.unwrap(), Some(PacketEvent::FromNat(packet(0, 5))));
+    assert_eq!(
+        events,
+        vec![
+            PacketEvent::ToNat(packet(3, 2)),
+            PacketEvent::FromNat(packet(0, 2)),
+            PacketEvent::ToNat(packet(3, 5)),
+            PacketEvent::FromNat(packet(0, 5)),
+        ]
+    );
+}
+
+#[test]
+fn test_packet_network_interrupted() {
+    let program = assemble(FORWARDER).unwrap();
+    // Runs rounds, until there are `count` events, giving node 3 a new budget whenever it runs out
+    let run = |budget, count| {
+        let mut network = PacketNetwork::new(&program, 4);
+        network.set_nat(3, 0);
+        network.machines_mut()[3].set_budget(budget);
+        let mut events = vec![];
+        let mut interruptions = 0;
+        while events.len() < count {
+            match network.round() {
+                Ok(round) => events.extend(round),
+                Err(NetworkError::Interrupted { node: 3 }) => {
+                    interruptions += 1;
+                    network.machines_mut()[3].set_budget(budget);
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+        let cycles: Vec<u64> = network.machines().iter().map(Machine::get_cycles).collect();
+        (events, cycles, interruptions)
+    };
+    let (expected, expected_cycles, _) = run(None, 4);
+    // Node 3 is interrupted in every turn, after the packet was sent to the NAT
+    let (events, cycles, interruptions) = run(Some(3), 4);
+    assert!(interruptions > 0);
+    assert_eq!(events, expected);
+    // The other nodes get no extra turns
+    assert_eq!(cycles[..3], expected_cycles[..3]);
+}
+
+#[test]
+fn test_run_async() {
+    // Outputs the sum of two inputs
+    let program = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    let mut machine = Machine::from_program(&program);
+    let mut output = vec![];
+    let result = block_on(machine.run_async(IterStream(vec![20, 22].into_iter()), &mut output));
+    assert_eq!(result, Ok(StepResult::Halted));
+    assert_eq!(output, vec![42]);
+
+    // The input ends too early
+    let mut machine = Machine::from_program(&program);
+    let result = block_on(machine.run_async(IterStream(Some(1).into_iter()), vec![]));
+    assert_eq!(result, Ok(StepResult::NeedsInput));
+
+    // A watchpoint pauses the machine, until it is resumed
+    let mut machine = Machine::from_program(&program);
+    machine.add_watchpoint(13..14, WatchKind::Write);
+    let mut output = vec![];
+    let result = block_on(machine.run_async(IterStream(vec![20, 22].into_iter()), &mut output));
+    let hit = WatchHit {
+        ip: 4,
+        address: 13,
+        access: Access::Write,
+        old: 0,
+        new: 42,
+    };
+    assert_eq!(result, Ok(StepResult::Watchpoint(hit)));
+    assert!(output.is_empty());
+    let result = block_on(machine.run_async(IterStream(None.into_iter()), &mut output));
+    assert_eq!(result, Ok(StepResult::Halted));
+    assert_eq!(output, vec![42]);
+}
+
+#[test]
+fn test_executor_pipeline() {
+    // Outputs its input plus one
+    let program = Program::parse("3,9,1001,9,1,9,4,9,99,0").unwrap();
+    let mut executor = Executor::new();
+    let (first, mut receiver) = queue();
+    for _ in 0..1000 {
+        let (sender, next) = queue();
+        let mut machine = Machine::from_program(&program);
+        executor.spawn(async move {
+            machine.run_async(receiver, sender).await.unwrap();
+        });
+        receiver = next;
+    }
+    let result = Rc::new(RefCell::new(vec![]));
+    let sink = Rc::clone(&result);
+    executor.spawn(async move {
+        let mut machine = Machine::from_program(&Program::parse("3,5,4,5,99,0").unwrap());
+        let mut output = vec![];
+        machine.run_async(receiver, &mut output).await.unwrap();
+        *sink.borrow_mut() = output;
+    });
+    assert_eq!(executor.run(), 1001);
+    first.send(0);
+    assert_eq!(executor.run(), 0);
+    assert_eq!(*result.borrow(), vec![1000]);
+}
+
+#[test]
+fn test_executor_stalled() {
+    let program = Program::parse("3,11,3,12,1,11,12,13,4,13,99").unwrap();
+    let mut executor = Executor::new();
+    let (sender, receiver) = queue();
+    let mut machine = Machine::from_program(&program);
+    executor.spawn(async move {
+        machine.run_async(receiver, vec![]).await.unwrap();
+    });
+    sender.send(1);
+    // The second input never comes, but the sender is still alive
+    assert_eq!(executor.run(), 1);
+    drop(sender);
+    assert_eq!(executor.run(), 0);
+}
+
+#[test]
+fn test_budget() {
+    // Outputs the numbers from the input down to 1
+    let program = Program::parse("3,12,4,12,1001,12,-1,12,1005,12,2,99,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(3);
+    machine.set_budget(Some(5));
+    assert_eq!(machine.run_until_event(), StepResult::Output(3));
+    assert_eq!(
+        machine.run_until_outputs(2),
+        Err(StepResult::BudgetExhausted)
+    );
+    assert_eq!(machine.get_budget(), Some(0));
+    assert_eq!(machine.get_cycles(), 5);
+
+    // Resumed with a new budget, the batch of outputs is completed
+    machine.set_budget(Some(100));
+    assert_eq!(machine.run_until_outputs(2), Ok(vec![2, 1]));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+    assert_eq!(machine.get_cycles(), 11);
+    assert_eq!(machine.get_budget(), Some(94));
+}
+
+#[test]
+fn test_deadline() {
+    // Loops forever
+    let program = Program::parse("1105,1,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.set_deadline(Some(Instant::now() + Duration::from_millis(10)));
+    assert_eq!(machine.run_until_event(), StepResult::BudgetExhausted);
+    assert!(machine.get_cycles() > 0);
+    // A passed deadline stops the machine right away
+    let cycles = machine.get_cycles();
+    assert_eq!(machine.run_until_event(), StepResult::BudgetExhausted);
+    assert_eq!(machine.get_cycles(), cycles);
+
+    let mut machine = Machine::from_program(&program);
+    machine.set_budget(Some(1000));
+    let (_, output) = machine.try_run().unwrap();
+    assert!(output.is_empty());
+
+    let mut looping = Machine::from_program(&program);
+    looping.set_budget(Some(10));
+    let network = Network::new(vec![
+        Machine::from_program(&Program::from(vec![99])),
+        looping,
+    ]);
+    match network.run() {
+        Err(NetworkError::BudgetExhausted { node: 1, machines }) => {
+            assert_eq!(machines.len(), 2);
+            assert_eq!(machines[1].get_cycles(), 10);
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+fn run_debugger(program: &str, script: &str) -> String {
+    let program = Program::parse(program).unwrap();
+    let mut debugger = Debugger::new(Machine::from_program(&program));
+    let mut output = vec![];
+    debugger.run_script(script.as_bytes(), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_debugger_oversized_count() {
+    let program = Program::parse("1101,1,2,5,99,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.set_memory_limit(7);
+    let mut debugger = Debugger::new(machine);
+    let script = "l 0 4611686018427387904\nx 0 4611686018427387904\n\
+        w 9223372036854775807 9223372036854775807\nwl\nl 4 100\nx 5 3\nq\n";
+    let mut output = vec![];
+    debugger.run_script(script.as_bytes(), &mut output).unwrap();
+    // Listings stop at the memory limit, and cells beyond it are shown as `?`
+    let expected = "(icdb) Can not show more than 65536 at once
+(icdb) Can not show more than 65536 at once
+(icdb) (icdb) Watchpoint on 9223372036854775807..18446744073709551614 (Write)
+(icdb)        4: hlt
+       5: data 0
+       6: data 0
+(icdb) 5: 0 0 ?
+(icdb) 
+";
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn test_debugger_breakpoints() {
+    // Outputs the numbers from the input down to 1
+    let program = "3,12,4,12,1001,12,-1,12,1005,12,2,99,0";
+    let output = run_debugger(program, "i 2\nb 8\nc\nx 12\nc\nc\nc\nq\n");
+    let expected = "(icdb) (icdb) Breakpoint at 8
+(icdb) Output: 2
+Stopped at breakpoint 8
+=>*    8: jnz [12], #2
+(icdb) 12: 1
+(icdb) Output: 1
+Stopped at breakpoint 8
+=>*    8: jnz [12], #2
+(icdb) Halted
+=>    11: hlt
+(icdb) Halted
+=>    11: hlt
+(icdb) 
+";
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_debugger_single_step() {
+    let program = "3,12,4,12,1001,12,-1,12,1005,12,2,99,0";
+    let output = run_debugger(program, "s\ni 5\ns\nr\ns 2\nr\nn\nr\n");
+    let expected = "(icdb) Waiting for input
+=>     0: in [12]
+(icdb) (icdb) =>     2: out [12]
+(icdb) ip=2 rb=0 halted=false waiting=false
+(icdb) Output: 5
+=>     8: jnz [12], #2
+(icdb) ip=8 rb=0 halted=false waiting=false
+(icdb) Output: 4
+=>     4: add [12], #-1, [12]
+(icdb) ip=4 rb=0 halted=false waiting=false
+(icdb) 
+";
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_debugger_patch_memory() {
+    let program = "109,3,204,2,99,7";
+    let output = run_debugger(program, "l 0 3\nset 5 42\nx 3 3\nc\nr\no\nset -1 0\nfoo\n");
+    let expected = "(icdb) =>     0: arb #3
+       2: out rb+2
+       4: hlt
+(icdb) (icdb) 3: 2 99 42
+(icdb) Output: 42
+Halted
+=>     4: hlt
+(icdb) ip=4 rb=3 halted=true waiting=false
+(icdb) [42]
+(icdb) Invalid address: -1
+(icdb) Unknown command: foo, try `h`
+(icdb) 
+";
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_debugger_reverse() {
+    let program = "3,12,4,12,1001,12,-1,12,1005,12,2,99,0";
+    let output = run_debugger(program, "i 2\nc\nb 2\nrc\nrs\nx 12\nrc\nrs 3\nr\no\nq\n");
+    let expected = "(icdb) (icdb) Output: 2
+Output: 1
+Halted
+=>    11: hlt
+(icdb) Breakpoint at 2
+(icdb) Stopped at breakpoint 2
+=>*    2: out [12]
+(icdb) =>     8: jnz [12], #2
+(icdb) 12: 1
+(icdb) Stopped at breakpoint 2
+=>*    2: out [12]
+(icdb) Reached the start of the history
+=>     0: in [12]
+(icdb) ip=0 rb=0 halted=false waiting=false
+(icdb) []
+(icdb) 
+";
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_debugger_watchpoints() {
+    let program = "3,12,4,12,1001,12,-1,12,1005,12,2,99,0";
+    let output = run_debugger(program, "i 2\nw 12\nrw 10 3\nwl\nc\nc\nwc\nc\nq\n");
+    let expected = "(icdb) (icdb) (icdb) (icdb) Watchpoint on 12..13 (Write)
+Watchpoint on 10..13 (Read)
+(icdb) Watchpoint: write [12]: 0 -> 2 at 0
+=>     2: out [12]
+(icdb) Output: 2
+Watchpoint: read [12] = 2 at 2
+=>     4: add [12], #-1, [12]
+(icdb) (icdb) Output: 1
+Halted
+=>    11: hlt
+(icdb) 
+";
+    assert_eq!(output, expected);
+}
+
 fn run_test(program: &str, expected_output: i64) {
     let program = String::from(program);
     let (_, output) = run_program(program, 0);
     assert_eq!(output, vec![expected_output]);
 }
+
+#[test]
+fn test_profiler() {
+    // Outputs the numbers from the input down to 1
+    let program = Program::parse("3,12,4,12,1001,12,-1,12,1005,12,2,99,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(3);
+    machine.enable_profiler();
+    assert_eq!(machine.run_until_outputs(3), Ok(vec![3, 2, 1]));
+    assert_eq!(machine.run_until_event(), StepResult::Halted);
+
+    let profile = machine.take_profile().unwrap();
+    assert!(machine.profile().is_none());
+    assert_eq!(profile.total(), 11);
+    assert_eq!(profile.executions(0), 1);
+    assert_eq!(profile.executions(4), 3);
+    assert_eq!(profile.executions(11), 1);
+    assert_eq!(profile.opcode_count(Opcode::OUT), 3);
+    assert_eq!(profile.opcode_count(Opcode::MUL), 0);
+    assert_eq!(profile.branch(8), (2, 1));
+    assert_eq!(profile.reads(12), 9);
+    assert_eq!(profile.writes(12), 4);
+    assert_eq!(profile.hot_spots(2), vec![(2, 3), (4, 3)]);
+
+    let mut report = vec![];
+    profile.write_report(&program, &mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.contains("3      8: jnz [12], #2  ; taken 2, not taken 1\n"));
+    assert!(report.contains("12: data 0  ; reads 9, writes 4\n"));
+    assert!(report.contains("3 out\n"));
+    assert!(report.ends_with("Total: 11 instructions\n"));
+}
+
+#[test]
+fn test_coverage() {
+    // Outputs 0 if the input is 0, and 1 otherwise
+    let program = Program::parse("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9").unwrap();
+    let run = |input| {
+        let mut machine = Machine::from_program(&program);
+        machine.push_input(input);
+        machine.enable_profiler();
+        while machine.run_until_event() != StepResult::Halted {}
+        machine.take_profile().unwrap().coverage()
+    };
+
+    let zero = run(0);
+    assert_eq!(zero.runs(), 1);
+    assert!(zero.is_executed(2));
+    assert!(!zero.is_executed(5));
+    assert_eq!(zero.branch(2), (1, 0));
+    assert_eq!(zero.data_only(), vec![12, 13, 15]);
+
+    let mut coverage = Coverage::new();
+    coverage.merge(&zero);
+    coverage.merge(&run(5));
+    assert_eq!(coverage.runs(), 2);
+    assert_eq!(coverage.executions(0), 2);
+    assert_eq!(coverage.executions(5), 1);
+    assert_eq!(coverage.branch(2), (1, 1));
+    assert!(coverage.is_data(14));
+    assert_eq!(coverage.data_only(), vec![12, 13, 14, 15]);
+
+    let mut listing = vec![];
+    zero.write_listing(&program, &mut listing).unwrap();
+    let listing = String::from_utf8(listing).unwrap();
+    assert!(listing.contains("1      2: jz [12], [15]  ; taken 1, not taken 0\n"));
+    assert!(listing.contains("#####      5: add [13], [14], [13]\n"));
+    assert!(listing.contains("-     15: data 9  ; data\n"));
+    assert!(listing.contains("Executed 4 of 5 instructions (80.0%) in 1 run\n"));
+
+    let mut lcov = vec![];
+    coverage.write_lcov(&program, "test", &mut lcov).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert!(lcov.starts_with("TN:\nSF:test\nBRDA:2,0,0,1\nBRDA:2,0,1,1\nBRF:2\nBRH:2\n"));
+    assert!(lcov.contains("DA:5,1\n"));
+    assert!(lcov.ends_with("LF:5\nLH:5\nend_of_record\n"));
+}
+
+#[test]
+fn test_coverage_data_section() {
+    // Echoes the input, followed by data, which decodes as an add and a conditional jump
+    let program = Program::parse("3,12,4,12,99,1,12,12,12,1005,12,0,0").unwrap();
+    let mut machine = Machine::from_program(&program);
+    machine.push_input(7);
+    machine.enable_profiler();
+    while machine.run_until_event() != StepResult::Halted {}
+    let coverage = machine.take_profile().unwrap().coverage();
+
+    let mut listing = vec![];
+    coverage.write_listing(&program, &mut listing).unwrap();
+    let listing = String::from_utf8(listing).unwrap();
+    assert!(listing.contains("-      5: add [12], [12], [12]\n"));
+    assert!(listing.contains("Executed 3 of 3 instructions (100.0%) in 1 run\n"));
+    assert!(listing.contains("Took 0 of 0 branches (-)\n"));
+
+    let mut lcov = vec![];
+    coverage.write_lcov(&program, "test", &mut lcov).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert!(lcov.contains("BRF:0\n"));
+    assert!(lcov.ends_with("DA:4,1\nLF:3\nLH:3\nend_of_record\n"));
+}
+
+const COUNTDOWN: &str = "
+        in [n]
+loop:   jz [n], #done
+        out [n]
+        add [n], #-1, [n]
+        jnz #1, #loop
+done:   jz #0, [ret]
+        hlt
+        hlt
+ret:    db 18
+n:      db 0
+";
+
+#[test]
+fn test_cfg() {
+    let program = assemble(COUNTDOWN).unwrap();
+    let cfg = Cfg::new(&program);
+    let starts: Vec<usize> = cfg.blocks().map(|block| block.start).collect();
+    assert_eq!(starts, vec![0, 2, 5, 14]);
+    let edge = |target, kind| Edge { target, kind };
+    assert_eq!(
+        cfg.block(0).unwrap().successors,
+        vec![edge(2, EdgeKind::Next)]
+    );
+    assert_eq!(
+        cfg.block(2).unwrap().successors,
+        vec![edge(14, EdgeKind::Taken), edge(5, EdgeKind::Next)]
+    );
+    let body = cfg.block(5).unwrap();
+    assert_eq!(body.lines.len(), 3);
+    assert_eq!(body.end(), 14);
+    assert_eq!(body.successors, vec![edge(2, EdgeKind::Taken)]);
+    assert_eq!(cfg.unresolved(), vec![14]);
+    assert!(cfg.block(14).unwrap().successors.is_empty());
+
+    let code: Vec<String> = program.as_slice().iter().map(i64::to_string).collect();
+    let buffer = run_traced(&code.join(","), 2, 100);
+    let jumps = observed_jumps(buffer.records());
+    assert_eq!(jumps, vec![(2, 14), (11, 2), (14, 18)]);
+    // A jump to a negative address faults, so it has no target
+    let faulty = TraceRecord {
+        ip: 7,
+        opcode: Opcode::JIF,
+        operands: vec![1, -5],
+        write: None,
+        relative_base: 0,
+        self_modifying: false,
+    };
+    assert!(observed_jumps(&[faulty]).is_empty());
+    let cfg = Cfg::with_observed(&program, &jumps);
+    assert_eq!(
+        cfg.block(14).unwrap().successors,
+        vec![edge(18, EdgeKind::Observed)]
+    );
+    assert_eq!(cfg.block(18).unwrap().lines.len(), 1);
+    assert!(cfg.block(17).is_none());
+
+    let mut dot = vec![];
+    cfg.write_dot(&mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    assert!(dot.starts_with("digraph cfg {\n"));
+    assert!(dot.contains("    b2 -> b14 [label=\"jump\"];\n"));
+    assert!(dot.contains("    b14 -> b18 [label=\"jump\", style=dashed];\n"));
+    assert!(dot.contains("    b14 -> unknown [style=dotted, color=red];\n"));
+    assert!(dot.ends_with("}\n"));
+}
+
+#[test]
+fn test_self_modification() {
+    // The example of day 2, which overwrites an operand, and then its first opcode
+    let program = "1,9,10,3,2,3,11,0,99,30,40,50";
+    let mut machine = Machine::try_new(String::from(program), 0).unwrap();
+    machine.detect_self_modification(true);
+    machine.run_until_event();
+    let smc = |ip, address, kind| SelfModification { ip, address, kind };
+    assert_eq!(
+        machine.take_self_modification(),
+        Some(smc(0, 3, SmcKind::WroteCode))
+    );
+    assert_eq!(
+        machine.take_self_modification(),
+        Some(smc(4, 0, SmcKind::WroteCode))
+    );
+    assert_eq!(machine.take_self_modification(), None);
+
+    // Writes a halt, and then executes it
+    let mut machine = Machine::try_new(String::from("1101,4,95,4,0"), 0).unwrap();
+    machine.detect_self_modification(true);
+    machine.run_until_event();
+    let event = machine.take_self_modification().unwrap();
+    assert_eq!(event, smc(4, 4, SmcKind::ExecutedData));
+    assert_eq!(event.to_string(), "execution of written [4] at 4");
+
+    let cfg = Cfg::new(&Program::parse(program).unwrap());
+    let shared = find_shared_cells(&cfg);
+    assert_eq!(
+        shared,
+        vec![
+            SharedCell {
+                address: 0,
+                instruction: 0,
+                accesses: vec![(4, Access::Write)]
+            },
+            SharedCell {
+                address: 3,
+                instruction: 0,
+                accesses: vec![(0, Access::Write), (4, Access::Read)]
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_self_modification_trace() {
+    let buffer = Arc::new(Mutex::new(TraceBuffer::new(10)));
+    let mut machine = Machine::try_new(String::from("1,9,10,3,2,3,11,0,99,30,40,50"), 0).unwrap();
+    machine.set_tracer(Box::new(buffer.clone()));
+    machine.detect_self_modification(true);
+    machine.run_until_event();
+    let buffer = buffer.lock().unwrap();
+    let flags: Vec<bool> = buffer.records().map(|r| r.self_modifying).collect();
+    assert_eq!(flags, vec![true, true, false]);
+
+    let record = buffer.records().next().unwrap();
+    assert_eq!(
+        record.to_json(),
+        "{\"ip\":0,\"op\":\"add\",\"operands\":[30,40,3],\"write\":[3,70],\"rb\":0,\"smc\":true}"
+    );
+    let mut binary = vec![];
+    buffer.write_to(&mut binary, TraceFormat::Binary).unwrap();
+    let records = read_binary_trace(&binary[..]).unwrap();
+    let expected: Vec<TraceRecord> = buffer.records().cloned().collect();
+    assert_eq!(records, expected);
+}
+
+#[test]
+fn test_debugger_self_modification() {
+    let output = run_debugger("1,9,10,3,2,3,11,0,99,30,40,50", "s\nsc\nq\n");
+    let expected = "(icdb) Self-modifying code: write to executed [3] at 0
+=>     4: mul [3], [11], [0]
+(icdb) [0] in the instruction at 0, written at 4
+[3] in the instruction at 0, read at 4
+(icdb) 
+";
+    assert_eq!(output, expected);
+}
+
+const SIGNS: &str = "
+        arb #stack
+loop:   in [n]
+        jz [n], #done
+        add [n], #0, rb+1
+        add #back, #0, rb+0
+        jz #0, #sign
+back:   out rb+1
+        jz #0, #loop
+done:   hlt
+sign:   arb #2
+        lt rb-1, #0, [neg]
+        jz [neg], #pos
+        mul #-1, #1, rb-1
+        jz #0, #end
+pos:    add #1, #0, rb-1
+end:    arb #-2
+        jz #0, rb+0
+n:      db 0
+neg:    db 0
+stack:  db 0
+";
+
+#[test]
+fn test_decompile() {
+    let program = assemble(SIGNS).unwrap();
+    let expected = "\
+fn main() {
+    rb += 51;
+    loop {
+        v49 = input();
+        if (v49 == 0) {
+            break;
+        }
+        f24(v49);
+        output(fp[1]);
+    }
+    halt;
+}
+
+fn f24() {
+    // frame of 2 cells
+    if (fp[-1] < 0) {
+        fp[-1] = -1;
+    } else {
+        fp[-1] = 1;
+    }
+    return;
+}
+";
+    assert_eq!(decompile(&program, &[]), expected);
+
+    // The return address is only known at runtime, unless it was seen in a trace
+    let program = assemble(COUNTDOWN).unwrap();
+    let expected = "\
+fn main() {
+    v20 = input();
+    while (v20 != 0) {
+        output(v20);
+        v20 = v20 - 1;
+    }
+    goto *v19;
+}
+";
+    assert_eq!(decompile(&program, &[]), expected);
+    let expected = expected.replace(";\n}", ";\nL18:\n    halt;\n}");
+    assert_eq!(decompile(&program, &[(14, 18)]), expected);
+}
+
+#[test]
+fn test_predecode() {
+    let outputs = |machine: &mut Machine| {
+        let mut outputs = vec![];
+        while let StepResult::Output(value) = machine.run_until_event() {
+            outputs.push(value);
+        }
+        outputs
+    };
+
+    // Outputs the operand of its OUT instruction, and increments it, while it is below 3
+    let program = "104,0,1001,1,1,1,1007,1,3,14,1005,14,0,99,0";
+    for &predecode in &[false, true] {
+        let mut machine = Machine::try_new(String::from(program), 0).unwrap();
+        machine.predecode(predecode);
+        let start = machine.snapshot();
+        assert_eq!(outputs(&mut machine), vec![0, 1, 2]);
+        assert_eq!(machine.get_cycles(), 13);
+
+        // Writes from outside and restoring a snapshot also change the decoded program
+        machine.restore(&start);
+        assert_eq!(machine.run_until_event(), StepResult::Output(0));
+        machine.set_memory(1, 10);
+        assert_eq!(outputs(&mut machine), vec![]);
+        machine.restore(&start);
+        assert_eq!(outputs(&mut machine), vec![0, 1, 2]);
+    }
+
+    // The example of day 2 overwrites an operand, and then its first opcode
+    let program = "1,9,10,3,2,3,11,0,99,30,40,50";
+    let mut machine = Machine::try_new(String::from(program), 0).unwrap();
+    machine.predecode(true);
+    let (memory, _) = machine.try_run().unwrap();
+    assert_eq!(memory, vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
+
+    // Errors are the same as without decoding ahead
+    let mut machine = Machine::try_new(String::from("1101,1,2,5,1105,5"), 0).unwrap();
+    machine.predecode(true);
+    machine.set_memory_limit(6);
+    assert_eq!(
+        machine.try_step(),
+        Err(IntcodeError::InvalidAddress {
+            ip: 4,
+            opcode: 1105,
+            operand: 1,
+            address: 6
+        })
+    );
+}
+
+#[test]
+fn test_debugger_budget() {
+    // Loops forever
+    let mut machine = Machine::from_program(&Program::parse("1105,1,0").unwrap());
+    machine.set_budget(Some(10));
+    let mut debugger = Debugger::new(machine);
+    let mut output = vec![];
+    debugger
+        .run_script("c\nc\nq\n".as_bytes(), &mut output)
+        .unwrap();
+    let stop = "Budget exhausted\n=>     0: jnz #1, #0\n(icdb) ";
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!("(icdb) {}{}\n", stop, stop)
+    );
+    assert_eq!(debugger.machine().get_cycles(), 10);
+}
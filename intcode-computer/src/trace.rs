@@ -0,0 +1,266 @@
+use crate::instruction::Opcode;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// The flag of a binary record with a write
+const FLAG_WRITE: i64 = 1;
+/// The flag of a binary record of a self-modifying instruction
+const FLAG_SMC: i64 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single instruction, executed by a traced machine
+pub struct TraceRecord {
+    /// The address of the instruction
+    pub ip: usize,
+    pub opcode: Opcode,
+    /// The resolved arguments: values for read arguments, addresses for the written argument.
+    /// The target of a conditional jump is only included, if the jump was taken.
+    pub operands: Vec<i64>,
+    /// The address and value written to memory, if any
+    pub write: Option<(usize, i64)>,
+    /// The relative base, after the instruction was executed
+    pub relative_base: i64,
+    /// True, if the instruction wrote to code, or was written itself, as found by a machine,
+    /// which detects self-modifying code
+    pub self_modifying: bool,
+}
+
+impl TraceRecord {
+    /// Formats the record as a single line of JSON, without a trailing newline.
+    /// `"smc":true` is only added to self-modifying instructions.
+    pub fn to_json(&self) -> String {
+        let operands: Vec<String> = self.operands.iter().map(ToString::to_string).collect();
+        let write = match self.write {
+            Some((address, value)) => format!("[{},{}]", address, value),
+            None => String::from("null"),
+        };
+        let smc = if self.self_modifying {
+            ",\"smc\":true"
+        } else {
+            ""
+        };
+        format!(
+            "{{\"ip\":{},\"op\":\"{}\",\"operands\":[{}],\"write\":{},\"rb\":{}{}}}",
+            self.ip,
+            self.opcode.mnemonic(),
+            operands.join(","),
+            write,
+            self.relative_base,
+            smc
+        )
+    }
+
+    /// Writes the record to `writer`, in `format`
+    pub fn write_to<W: Write>(&self, writer: &mut W, format: TraceFormat) -> io::Result<()> {
+        match format {
+            TraceFormat::JsonLines => writeln!(writer, "{}", self.to_json()),
+            TraceFormat::Binary => self.write_binary(writer),
+        }
+    }
+
+    /// Writes the record in the compact binary format.
+    /// Every number is a zigzag encoded LEB128 varint: the ip, the opcode, the number of
+    /// operands, the operands, the flags (1 for a write, plus 2 for a self-modifying
+    /// instruction), the written address and value (if present) and the relative base.
+    pub fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut buf = vec![];
+        write_varint(&mut buf, self.ip as i64);
+        write_varint(&mut buf, self.opcode.code());
+        write_varint(&mut buf, self.operands.len() as i64);
+        for &operand in &self.operands {
+            write_varint(&mut buf, operand);
+        }
+        let smc_flag = if self.self_modifying { FLAG_SMC } else { 0 };
+        match self.write {
+            Some((address, value)) => {
+                write_varint(&mut buf, FLAG_WRITE | smc_flag);
+                write_varint(&mut buf, address as i64);
+                write_varint(&mut buf, value);
+            }
+            None => write_varint(&mut buf, smc_flag),
+        }
+        write_varint(&mut buf, self.relative_base);
+        writer.write_all(&buf)
+    }
+
+    /// Reads a record written by `write_binary`. Returns `None` at the end of `reader`.
+    pub fn read_binary<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let ip = match read_varint(reader)? {
+            Some(ip) => ip as usize,
+            None => return Ok(None),
+        };
+        let opcode = Opcode::try_from(read_required(reader)?)
+            .map_err(|code| invalid_data(format!("Unknown opcode in trace: {}", code)))?;
+        let num_operands = read_required(reader)?;
+        let operands = (0..num_operands)
+            .map(|_| read_required(reader))
+            .collect::<io::Result<_>>()?;
+        let flags = read_required(reader)?;
+        let write = match flags & FLAG_WRITE {
+            0 => None,
+            _ => Some((read_required(reader)? as usize, read_required(reader)?)),
+        };
+        let relative_base = read_required(reader)?;
+        Ok(Some(Self {
+            ip,
+            opcode,
+            operands,
+            write,
+            relative_base,
+            self_modifying: flags & FLAG_SMC != 0,
+        }))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_varint(buf: &mut Vec<u8>, n: i64) {
+    let mut zigzag = ((n << 1) ^ (n >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Reads a varint. Returns `None`, if `reader` ends before its first byte.
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<Option<i64>> {
+    let mut zigzag = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if shift >= 64 {
+            return Err(invalid_data(String::from("Varint in trace is too long")));
+        }
+        zigzag |= u64::from(byte[0] & 0x7f) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64)));
+        }
+    }
+}
+
+fn read_required<R: Read>(reader: &mut R) -> io::Result<i64> {
+    read_varint(reader)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+/// Reads every record of a binary trace
+pub fn read_binary_trace<R: Read>(mut reader: R) -> io::Result<Vec<TraceRecord>> {
+    let mut records = vec![];
+    while let Some(record) = TraceRecord::read_binary(&mut reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One JSON object per line
+    JsonLines,
+    /// See `TraceRecord::write_binary`
+    Binary,
+}
+
+/// A receiver of the instructions executed by a machine. See `Machine::set_tracer`.
+pub trait Tracer: Debug {
+    fn record(&mut self, record: TraceRecord);
+}
+
+/// Allows keeping access to a tracer, which is attached to a machine
+impl<T: Tracer> Tracer for Arc<Mutex<T>> {
+    fn record(&mut self, record: TraceRecord) {
+        self.lock().expect("Tracer lock is poisoned").record(record);
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A tracer, which keeps the last `capacity` records
+pub struct TraceBuffer {
+    capacity: usize,
+    records: VecDeque<TraceRecord>,
+}
+
+impl TraceBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// The kept records, from oldest to newest
+    pub fn records(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.records.iter()
+    }
+
+    /// Writes the kept records to `writer`, in `format`
+    pub fn write_to<W: Write>(&self, mut writer: W, format: TraceFormat) -> io::Result<()> {
+        for record in &self.records {
+            record.write_to(&mut writer, format)?;
+        }
+        writer.flush()
+    }
+}
+
+impl Tracer for TraceBuffer {
+    fn record(&mut self, record: TraceRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+}
+
+#[derive(Debug)]
+/// A tracer, which streams every record to a writer.
+/// Since tracing can not fail, the first I/O error is kept, and can be checked with `finish`.
+pub struct TraceWriter<W: Write + Debug> {
+    writer: W,
+    format: TraceFormat,
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Debug> TraceWriter<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Self {
+        Self {
+            writer,
+            format,
+            error: None,
+        }
+    }
+
+    /// Flushes the writer, and returns it, or the first error which occurred while tracing.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write + Debug> Tracer for TraceWriter<W> {
+    fn record(&mut self, record: TraceRecord) {
+        if self.error.is_none() {
+            self.error = record.write_to(&mut self.writer, self.format).err();
+        }
+    }
+}
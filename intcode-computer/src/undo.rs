@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+/// The state overwritten by a single executed instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UndoRecord {
+    pub(crate) ip: usize,
+    pub(crate) relative_base: i64,
+    /// The written address, and the value it held before
+    pub(crate) write: Option<(usize, i64)>,
+    /// The value taken from the front of the input queue
+    pub(crate) input: Option<i64>,
+    /// Set if a value was appended to the output
+    pub(crate) output: bool,
+    /// Set if the output value was also kept by an interrupted `run_until_outputs`
+    pub(crate) pending_output: bool,
+}
+
+/// The most recent undo records of a machine, oldest first.
+/// Once `capacity` records are held, the oldest one is dropped for every new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UndoLog {
+    capacity: usize,
+    records: VecDeque<UndoRecord>,
+}
+
+impl UndoLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, record: UndoRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<UndoRecord> {
+        self.records.pop_back()
+    }
+
+    /// Marks the output of the last record as kept by `run_until_outputs`
+    pub(crate) fn mark_pending_output(&mut self) {
+        if let Some(record) = self.records.back_mut() {
+            record.pending_output = true;
+        }
+    }
+
+    /// Forgets, which outputs were kept by `run_until_outputs`, once it returned them
+    pub(crate) fn clear_pending_outputs(&mut self) {
+        for record in &mut self.records {
+            record.pending_output = false;
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.records.clear();
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+
+/// A single access to a memory cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// The accesses, which trigger a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+/// An access to a watched memory cell.
+/// For a read, `old` and `new` are both the value read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The address of the accessing instruction
+    pub ip: usize,
+    pub address: usize,
+    pub access: Access,
+    pub old: i64,
+    pub new: i64,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(f, "read [{}] = {}", self.address, self.old),
+            Access::Write => write!(f, "write [{}]: {} -> {}", self.address, self.old, self.new),
+        }?;
+        write!(f, " at {}", self.ip)
+    }
+}
+
+type Callback = Box<dyn FnMut(WatchHit) + Send>;
+
+/// A watched address range. Without a callback, a hit pauses the machine.
+struct Watchpoint {
+    range: Range<usize>,
+    kind: WatchKind,
+    callback: Option<Callback>,
+}
+
+impl fmt::Debug for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watchpoint")
+            .field("range", &self.range)
+            .field("kind", &self.kind)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+/// The watchpoints of a machine, and the hits, which have not been reported yet
+#[derive(Debug, Default)]
+pub(crate) struct Watchpoints {
+    entries: Vec<Watchpoint>,
+    hits: VecDeque<WatchHit>,
+}
+
+impl Watchpoints {
+    pub(crate) fn add(&mut self, range: Range<usize>, kind: WatchKind, callback: Option<Callback>) {
+        self.entries.push(Watchpoint {
+            range,
+            kind,
+            callback,
+        });
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.hits.clear();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn list(&self) -> Vec<(Range<usize>, WatchKind)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.range.clone(), entry.kind))
+            .collect()
+    }
+
+    /// Passes `hit` to the callbacks of the matching watchpoints.
+    /// If any matching watchpoint has no callback, the hit is kept to be reported.
+    pub(crate) fn check(&mut self, hit: WatchHit) {
+        let mut pause = false;
+        for entry in &mut self.entries {
+            if !entry.range.contains(&hit.address) || !entry.kind.matches(hit.access) {
+                continue;
+            }
+            match &mut entry.callback {
+                Some(callback) => callback(hit),
+                None => pause = true,
+            }
+        }
+        if pause {
+            self.hits.push_back(hit);
+        }
+    }
+
+    pub(crate) fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hits.pop_front()
+    }
+
+    pub(crate) fn clear_hits(&mut self) {
+        self.hits.clear();
+    }
+}